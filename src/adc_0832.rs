@@ -1,5 +1,10 @@
+// ADC0831/2/4/8 serial 8 bit ADC family. The 0832 is the one on the breakout board.
+// Datasheet: https://www.ti.com/lit/ds/symlink/adc0838-n.pdf
+
+use crate::analog::{voltage, AdcError, AnalogInput, Input};
 use gpio::GpioValue::{High, Low};
 use gpio::{GpioIn, GpioOut};
+use spidev::{SpiModeFlags, Spidev, SpidevOptions, SpidevTransfer};
 use std::thread;
 use std::time::Duration;
 
@@ -7,79 +12,283 @@ const CS_PIN: u16 = 16;
 const CLK_PIN: u16 = 20;
 const DIO_PIN: u16 = 21;
 
+// Well inside the 400 kHz the chip allows at 5 V; it is slower at 3.3 V.
+const SPI_SPEED_HZ: u32 = 200_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Part {
+    Adc0832,
+    Adc0834,
+    Adc0838,
+}
+
+impl Part {
+    pub fn channels(&self) -> u8 {
+        match self {
+            Part::Adc0832 => 2,
+            Part::Adc0834 => 4,
+            Part::Adc0838 => 8,
+        }
+    }
+}
+
+/// Bits clocked in after the start bit: SGL/DIF, ODD/SIGN, then SELECT on the larger parts.
+pub fn mux_bits(part: Part, input: Input) -> Result<Vec<bool>, AdcError> {
+    let (single, channel) = match input {
+        Input::SingleEnded(channel) => (true, channel),
+        Input::Differential(channel) => (false, channel),
+    };
+    if channel >= part.channels() {
+        return Err(AdcError::BadChannel {
+            channel,
+            channels: part.channels(),
+        });
+    }
+    let odd = channel & 1 == 1;
+    Ok(match part {
+        Part::Adc0832 => Vec::from([single, odd]),
+        Part::Adc0834 => Vec::from([single, odd, channel & 2 != 0]),
+        // SELECT1 picks the half, SELECT0 the pair within it.
+        Part::Adc0838 => Vec::from([single, odd, channel & 4 != 0, channel & 2 != 0]),
+    })
+}
+
+/// How the chip is wired to the Pi.
+enum Backend {
+    /// CS, CLK and a shared DIO on plain GPIO pins.
+    Gpio {
+        cs: gpio::sysfs::SysFsGpioOutput,
+        clk: gpio::sysfs::SysFsGpioOutput,
+    },
+    /// The SPI controller, DI on MOSI and DO on MISO.
+    Spi(Spidev),
+}
+
 pub struct ADC {
-    cs: gpio::sysfs::SysFsGpioOutput,
-    clk: gpio::sysfs::SysFsGpioOutput,
+    backend: Backend,
+    part: Part,
+    vref: f32,
+}
+
+/// Transmit frame for one conversion, and the bit where the MSB first result starts.
+///
+/// Leading zeros pad the start bit to a whole number of bytes; the chip ignores them.
+/// After the mux bits come a null bit, D7..D0, then D1..D7 again LSB first.
+pub fn spi_frame(part: Part, input: Input) -> Result<(Vec<u8>, usize), AdcError> {
+    let bits = mux_bits(part, input)?;
+    let clocks = 1 + bits.len() + 1 + 8 + 7;
+    let bytes = clocks.div_ceil(8);
+    let start = bytes * 8 - clocks;
+    let mut frame = vec![0_u8; bytes];
+    for (i, bit) in std::iter::once(true).chain(bits).enumerate() {
+        if bit {
+            let position = start + i;
+            frame[position / 8] |= 0x80 >> (position % 8);
+        }
+    }
+    Ok((frame, bytes * 8 - 15))
+}
+
+/// The MSB first and LSB first copies from a received frame, `data` as from `spi_frame`.
+pub fn spi_decode(received: &[u8], data: usize) -> (u8, u8) {
+    let bit = |position: usize| (received[position / 8] >> (7 - position % 8)) & 1;
+    let msb_first = (0..8).fold(0, |value, i| (value << 1) | bit(data + i));
+    // D0 is shared, so the LSB first copy starts with D1.
+    let lsb_first = (1..8).fold(bit(data + 7), |value, i| value | (bit(data + 7 + i) << i));
+    (msb_first, lsb_first)
+}
+
+fn clock_in(
+    clk: &mut gpio::sysfs::SysFsGpioOutput,
+    data_out: &mut gpio::sysfs::SysFsGpioOutput,
+    bit: bool,
+) {
+    data_out.set_value(bit).expect("Pin should set");
+    thread::sleep(Duration::from_micros(2));
+    clk.set_value(High).expect("Pin should set");
+    thread::sleep(Duration::from_micros(2));
+    clk.set_value(Low).expect("Pin should set");
+}
+
+/// Clock out the start and mux bits by hand and return both copies of the result.
+fn bit_bang(
+    cs: &mut gpio::sysfs::SysFsGpioOutput,
+    clk: &mut gpio::sysfs::SysFsGpioOutput,
+    bits: Vec<bool>,
+) -> (u8, u8) {
+    let mut data_out = gpio::sysfs::SysFsGpioOutput::open(DIO_PIN).expect("Pin should be active");
+    cs.set_value(Low).expect("Pin should set");
+
+    clk.set_value(Low).expect("Pin should set");
+    // Start bit.
+    clock_in(clk, &mut data_out, true);
+    for bit in bits {
+        clock_in(clk, &mut data_out, bit);
+    }
+    // Let the multiplexer settle before the first data bit.
+    data_out.set_value(High).expect("Pin should set");
+    thread::sleep(Duration::from_micros(2));
+
+    let mut msb_first: u8 = 0;
+    let mut data_in = gpio::sysfs::SysFsGpioInput::open(DIO_PIN).expect("Pin is active");
+    for _ in 0..8 {
+        clk.set_value(High).expect("Pin should set");
+        thread::sleep(Duration::from_micros(2));
+        clk.set_value(Low).expect("Pin should set");
+        thread::sleep(Duration::from_micros(2));
+        match data_in.read_value().expect("Pin should read") {
+            High => msb_first = (msb_first << 1) | 1,
+            Low => msb_first <<= 1,
+        }
+    }
+    // The chip then repeats the conversion LSB first, sharing bit 0.
+    let mut lsb_first: u8 = 0;
+    for i in 0..8 {
+        if data_in.read_value().expect("Pin should read") == High {
+            lsb_first |= 1 << i;
+        }
+        clk.set_value(High).expect("Pin should set");
+        thread::sleep(Duration::from_micros(2));
+        clk.set_value(Low).expect("Pin should set");
+        thread::sleep(Duration::from_micros(2));
+    }
+    cs.set_value(High).expect("Pin should set");
+    (msb_first, lsb_first)
 }
 
 impl ADC {
     pub fn new() -> ADC {
+        ADC::with_part(Part::Adc0832)
+    }
+
+    /// Bit-banged on the CS, CLK and DIO pins.
+    pub fn with_part(part: Part) -> ADC {
         let cs = gpio::sysfs::SysFsGpioOutput::open(CS_PIN).expect("Pin should be active");
         let clk = gpio::sysfs::SysFsGpioOutput::open(CLK_PIN).expect("Pin should be active");
 
-        Self { cs, clk }
+        Self {
+            backend: Backend::Gpio { cs, clk },
+            part,
+            vref: 3.3,
+        }
     }
 
-    pub fn get_result(&mut self, channel: u8) -> u8 {
-        let mut data_out =
-            gpio::sysfs::SysFsGpioOutput::open(DIO_PIN).expect("Pin should be active");
-        self.cs.set_value(Low).expect("Pin should set");
+    /// On a hardware SPI device such as "/dev/spidev0.0", one transfer per conversion.
+    pub fn with_spi(part: Part, path: &str) -> ADC {
+        let mut spi = Spidev::open(path).expect("Device should be found");
+        let options = SpidevOptions::new()
+            .bits_per_word(8)
+            .max_speed_hz(SPI_SPEED_HZ)
+            .mode(SpiModeFlags::SPI_MODE_0)
+            .build();
+        spi.configure(&options).expect("Device should configure");
 
-        self.clk.set_value(Low).expect("Pin should set");
-        data_out.set_value(High).expect("Pin should set");
-        thread::sleep(Duration::from_micros(2));
-        self.clk.set_value(High).expect("Pin should set"); // 1
-        thread::sleep(Duration::from_micros(2));
-        self.clk.set_value(Low).expect("Pin should set");
+        Self {
+            backend: Backend::Spi(spi),
+            part,
+            vref: 3.3,
+        }
+    }
 
-        data_out.set_value(High).expect("Pin should set");
-        thread::sleep(Duration::from_micros(2));
-        self.clk.set_value(High).expect("Pin should set"); // 2
-        thread::sleep(Duration::from_micros(2));
-        self.clk.set_value(Low).expect("Pin should set");
+    /// Reference voltage, full scale for `get_voltage`. Defaults to the 3.3 V rail.
+    pub fn with_vref(mut self, vref: f32) -> ADC {
+        self.vref = vref;
+        self
+    }
 
-        match channel {
-            0 => data_out.set_value(Low).expect("Pin should set"),
-            1 => data_out.set_value(High).expect("Pin should set"),
-            _ => panic!(),
-        }
-        thread::sleep(Duration::from_micros(2));
+    pub fn part(&self) -> Part {
+        self.part
+    }
 
-        self.clk.set_value(High).expect("Pin should set"); // 3
-        data_out.set_value(High).expect("Pin should set");
-        thread::sleep(Duration::from_micros(2));
-        self.clk.set_value(Low).expect("Pin should set");
-        data_out.set_value(High).expect("Pin should set");
-        thread::sleep(Duration::from_micros(2));
+    pub fn vref(&self) -> f32 {
+        self.vref
+    }
 
-        let mut lsb_data: u8 = 0;
-        let mut data_in = gpio::sysfs::SysFsGpioInput::open(DIO_PIN).expect("Pin is active");
-        for _ in 0..8 {
-            self.clk.set_value(High).expect("Pin should set");
-            thread::sleep(Duration::from_micros(2));
-            self.clk.set_value(Low).expect("Pin should set");
-            thread::sleep(Duration::from_micros(2));
-            match data_in.read_value().expect("Pin should read") {
-                High => lsb_data = (lsb_data << 1) | 1,
-                Low => lsb_data = (lsb_data << 1) | 0,
+    /// Raw count, 0 to 255 of `vref`.
+    pub fn get_result(&mut self, input: Input) -> Result<u8, AdcError> {
+        let (msb_first, lsb_first) = match &mut self.backend {
+            Backend::Gpio { cs, clk } => bit_bang(cs, clk, mux_bits(self.part, input)?),
+            Backend::Spi(spi) => {
+                let (frame, data) = spi_frame(self.part, input)?;
+                let mut received = vec![0_u8; frame.len()];
+                let mut transfer = SpidevTransfer::read_write(&frame, &mut received);
+                spi.transfer(&mut transfer)
+                    .expect("Transfer should complete");
+                spi_decode(&received, data)
             }
-        }
-        let mut msb_data: u8 = 0;
-        for i in 0..8 {
-            match data_in.read_value().expect("Pin should read") {
-                High => msb_data = msb_data | (1 << i),
-                Low => msb_data = msb_data | (0 << i),
-            };
-            self.clk.set_value(High).expect("Pin should set");
-            thread::sleep(Duration::from_micros(2));
-            self.clk.set_value(Low).expect("Pin should set");
-            thread::sleep(Duration::from_micros(2));
-        }
-        self.cs.set_value(High).expect("Pin should set");
-        if lsb_data == msb_data {
-            lsb_data
+        };
+        if msb_first == lsb_first {
+            Ok(msb_first)
         } else {
-            0
+            Err(AdcError::Mismatch {
+                msb_first,
+                lsb_first,
+            })
         }
     }
+
+    pub fn get_voltage(&mut self, input: Input) -> Result<f32, AdcError> {
+        Ok(voltage(self.get_result(input)?, self.vref))
+    }
+}
+
+impl AnalogInput for ADC {
+    fn read(&mut self, input: Input) -> Result<u8, AdcError> {
+        self.get_result(input)
+    }
+
+    fn vref(&self) -> f32 {
+        self.vref
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mux_bits_match_datasheet_tables() {
+        assert_eq!(
+            mux_bits(Part::Adc0832, Input::SingleEnded(1)),
+            Ok(vec![true, true])
+        );
+        assert_eq!(
+            mux_bits(Part::Adc0834, Input::SingleEnded(2)),
+            Ok(vec![true, false, true])
+        );
+        // CH6 single ended is 1 0 1 1; CH5+ CH4- differential is 0 1 1 0.
+        assert_eq!(
+            mux_bits(Part::Adc0838, Input::SingleEnded(6)),
+            Ok(vec![true, false, true, true])
+        );
+        assert_eq!(
+            mux_bits(Part::Adc0838, Input::Differential(5)),
+            Ok(vec![false, true, true, false])
+        );
+    }
+
+    #[test]
+    fn test_mux_bits_rejects_missing_channel() {
+        assert_eq!(
+            mux_bits(Part::Adc0832, Input::Differential(2)),
+            Err(AdcError::BadChannel {
+                channel: 2,
+                channels: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_spi_frame_round_trip() {
+        // 5 padding zeros, start, SGL, ODD, null, then data from bit 9.
+        let (frame, data) = spi_frame(Part::Adc0832, Input::SingleEnded(1)).unwrap();
+        assert_eq!(frame, vec![0b0000_0111, 0, 0]);
+        assert_eq!(data, 9);
+        // 0xB5 MSB first, then bits 1 to 7 of it LSB first.
+        let received = [0b0000_0000, 0b0101_1010, 0b1010_1101];
+        assert_eq!(spi_decode(&received, data), (0xB5, 0xB5));
+        let (frame, data) = spi_frame(Part::Adc0838, Input::SingleEnded(6)).unwrap();
+        assert_eq!(frame, vec![0b0001_1011, 0, 0]);
+        assert_eq!(data, 9);
+    }
 }
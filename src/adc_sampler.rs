@@ -0,0 +1,289 @@
+// Continuous sampling of ADC channels into a ring buffer on a background thread.
+//
+// let mut sampler = Sampler::spawn(ADC::new(), vec![Input::SingleEnded(0)], 500.0, 1000)?;
+// thread::sleep(Duration::from_secs(1));
+// sampler.window(0, 100); // min/max/mean/RMS of the last 100 samples
+// sampler.timing();       // achieved rate and jitter
+
+use crate::analog::{voltage, AnalogInput, Input};
+use crate::ring::Ring;
+use crate::worker::Worker;
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplerError {
+    /// The rate was zero, negative or not a number.
+    BadRate(f32),
+}
+
+impl fmt::Display for SamplerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SamplerError::BadRate(rate) => write!(f, "sample rate {rate} is not a positive number"),
+        }
+    }
+}
+
+impl std::error::Error for SamplerError {}
+
+/// One reading of every selected input, in the order they were given.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub at: Instant,
+    pub raw: Vec<u8>,
+}
+
+/// Statistics over a run of values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Window {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    /// Root mean square, DC included.
+    pub rms: f32,
+    /// Root mean square about the mean, the AC part of the signal.
+    pub ac_rms: f32,
+}
+
+impl Window {
+    pub fn from_values(values: &[f32]) -> Option<Window> {
+        if values.is_empty() {
+            return None;
+        }
+        let count = values.len() as f32;
+        let mean = values.iter().sum::<f32>() / count;
+        let square = values.iter().map(|v| v * v).sum::<f32>() / count;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / count;
+        Some(Window {
+            min: values.iter().copied().fold(f32::INFINITY, f32::min),
+            max: values.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+            mean,
+            rms: square.sqrt(),
+            ac_rms: variance.sqrt(),
+        })
+    }
+}
+
+/// How closely sampling kept to its schedule.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timing {
+    /// Samples per second achieved.
+    pub rate: f32,
+    /// Standard deviation of the interval between samples.
+    pub jitter: Duration,
+}
+
+impl Timing {
+    /// Needs at least two timestamps, in order.
+    pub fn from_times(times: &[Instant]) -> Option<Timing> {
+        if times.len() < 2 {
+            return None;
+        }
+        let intervals: Vec<f32> = times
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).as_secs_f32())
+            .collect();
+        let count = intervals.len() as f32;
+        let mean = intervals.iter().sum::<f32>() / count;
+        let variance = intervals.iter().map(|i| (i - mean).powi(2)).sum::<f32>() / count;
+        Some(Timing {
+            rate: if mean > 0.0 { 1.0 / mean } else { 0.0 },
+            jitter: Duration::from_secs_f32(variance.sqrt()),
+        })
+    }
+}
+
+/// Time between samples at `rate` a second.
+pub fn period(rate: f32) -> Result<Duration, SamplerError> {
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(SamplerError::BadRate(rate));
+    }
+    Duration::try_from_secs_f32(1.0 / rate).map_err(|_| SamplerError::BadRate(rate))
+}
+
+/// Samples the inputs at a target rate until stopped.
+pub struct Sampler {
+    samples: Arc<Mutex<Ring<Sample>>>,
+    errors: Arc<AtomicU32>,
+    worker: Worker,
+    vref: f32,
+}
+
+impl Sampler {
+    /// Keep the newest `capacity` samples taken `rate` times a second.
+    ///
+    /// A read with mismatched MSB/LSB copies drops the whole sample and counts an error. A rate
+    /// that isn't a positive number is rejected.
+    pub fn spawn<A: AnalogInput + Send + 'static>(
+        mut adc: A,
+        inputs: Vec<Input>,
+        rate: f32,
+        capacity: usize,
+    ) -> Result<Sampler, SamplerError> {
+        let period = period(rate)?;
+        let vref = adc.vref();
+        let samples = Arc::new(Mutex::new(Ring::new(capacity)));
+        let errors = Arc::new(AtomicU32::new(0));
+        let thread_samples = Arc::clone(&samples);
+        let thread_errors = Arc::clone(&errors);
+        let worker = Worker::spawn("adc sampler", move |running| {
+            let mut next = Instant::now();
+            while running.load(Ordering::Relaxed) {
+                let at = Instant::now();
+                let raw: Result<Vec<u8>, _> = inputs.iter().map(|&input| adc.read(input)).collect();
+                match raw {
+                    Ok(raw) => thread_samples
+                        .lock()
+                        .expect("Lock should not be poisoned")
+                        .push(Sample { at, raw }),
+                    Err(_) => {
+                        thread_errors.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                // Schedule against the start time so delays don't accumulate,
+                // but don't try to catch up on missed slots.
+                next += period;
+                let now = Instant::now();
+                if next > now {
+                    thread::sleep(next - now);
+                } else {
+                    next = now;
+                }
+            }
+        });
+        Ok(Sampler {
+            samples,
+            errors,
+            worker,
+            vref,
+        })
+    }
+
+    /// Up to `count` of the newest samples, oldest first.
+    pub fn latest(&self, count: usize) -> Vec<Sample> {
+        let samples = self.samples.lock().expect("Lock should not be poisoned");
+        samples.last(count).cloned().collect()
+    }
+
+    /// Statistics in volts for the input at `index` over the newest `count` samples.
+    pub fn window(&self, index: usize, count: usize) -> Option<Window> {
+        let volts: Vec<f32> = self
+            .latest(count)
+            .iter()
+            .filter_map(|sample| sample.raw.get(index))
+            .map(|&raw| voltage(raw, self.vref))
+            .collect();
+        Window::from_values(&volts)
+    }
+
+    /// Rate and jitter over everything in the buffer.
+    pub fn timing(&self) -> Option<Timing> {
+        let samples = self.samples.lock().expect("Lock should not be poisoned");
+        let times: Vec<Instant> = samples.last(samples.len()).map(|s| s.at).collect();
+        Timing::from_times(&times)
+    }
+
+    /// Samples dropped for failed reads.
+    pub fn errors(&self) -> u32 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    pub fn stop(&mut self) {
+        self.worker.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analog::AdcError;
+
+    /// Counts up on every read, failing every fourth.
+    struct FakeAdc {
+        reads: u8,
+    }
+
+    impl AnalogInput for FakeAdc {
+        fn read(&mut self, _: Input) -> Result<u8, AdcError> {
+            self.reads = self.reads.wrapping_add(1);
+            match self.reads % 4 {
+                0 => Err(AdcError::Mismatch {
+                    msb_first: 0,
+                    lsb_first: 1,
+                }),
+                _ => Ok(self.reads),
+            }
+        }
+
+        fn vref(&self) -> f32 {
+            5.0
+        }
+    }
+
+    #[test]
+    fn test_sampler_thread() {
+        let mut sampler =
+            Sampler::spawn(FakeAdc { reads: 0 }, vec![Input::SingleEnded(0)], 1000.0, 8).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while (sampler.latest(8).len() < 8 || sampler.errors() < 2) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        sampler.stop();
+
+        let samples = sampler.latest(100);
+        assert_eq!(samples.len(), 8);
+        assert!(samples.windows(2).all(|pair| pair[0].at < pair[1].at));
+        assert!(samples.iter().all(|sample| sample.raw[0] % 4 != 0));
+        assert!(sampler.errors() >= 2);
+        let window = sampler.window(0, 8).expect("Window should exist");
+        assert!(window.min > 0.0 && window.max <= 5.0);
+
+        // Nothing more arrives once stopped.
+        let errors = sampler.errors();
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(sampler.latest(100), samples);
+        assert_eq!(sampler.errors(), errors);
+    }
+
+    #[test]
+    fn test_period_rejects_bad_rates() {
+        assert_eq!(period(500.0), Ok(Duration::from_millis(2)));
+        for rate in [0.0, -10.0, f32::NAN, f32::INFINITY] {
+            assert!(period(rate).is_err());
+        }
+    }
+
+    #[test]
+    fn test_window_of_square_wave() {
+        let window = Window::from_values(&[1.0, 3.0, 1.0, 3.0]).expect("Window should exist");
+        assert_eq!(window.min, 1.0);
+        assert_eq!(window.max, 3.0);
+        assert_eq!(window.mean, 2.0);
+        assert_eq!(window.rms, 5.0_f32.sqrt());
+        assert_eq!(window.ac_rms, 1.0);
+        assert_eq!(Window::from_values(&[]), None);
+    }
+
+    #[test]
+    fn test_timing() {
+        let start = Instant::now();
+        let times: Vec<Instant> = [0, 10, 20, 30, 40]
+            .iter()
+            .map(|&ms| start + Duration::from_millis(ms))
+            .collect();
+        let timing = Timing::from_times(&times).expect("Timing should exist");
+        assert!((timing.rate - 100.0).abs() < 0.01);
+        assert!(timing.jitter < Duration::from_micros(1));
+
+        let uneven: Vec<Instant> = [0, 5, 20, 25, 40]
+            .iter()
+            .map(|&ms| start + Duration::from_millis(ms))
+            .collect();
+        let timing = Timing::from_times(&uneven).expect("Timing should exist");
+        assert!((timing.jitter.as_secs_f32() - 0.005).abs() < 0.0001);
+    }
+}
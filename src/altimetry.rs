@@ -0,0 +1,271 @@
+// Altimetry from station pressure using the ICAO standard atmosphere.
+//
+// QNH: pressure reduced to sea level, read altitude against it.
+// QFE: pressure at the field itself, read height above it.
+// https://www.weather.gov/media/epz/wxcalc/pressureAltitude.pdf
+// https://www.weather.gov/media/epz/wxcalc/densityAltitude.pdf
+
+use crate::barometer::{detect, PressureSensor};
+use crate::config::Config;
+use crate::filter::Iir;
+use crate::lcd::LCD;
+use crate::pressure_filter::{Filter, Filtered};
+use crate::psychrometrics::vapor_pressure;
+use gpio::GpioOut;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const STANDARD_PRESSURE: f32 = 101_325.0;
+
+// ISA troposphere; metres of altitude for the pressure ratio raised to EXPONENT.
+const SCALE_HEIGHT: f32 = 44_330.77;
+const EXPONENT: f32 = 0.190_263;
+
+// Specific gas constants for dry air and water vapor, J/(kg K).
+const R_DRY: f32 = 287.058;
+const R_VAPOR: f32 = 461.495;
+
+const BUZZER_PIN: u16 = 26;
+
+/// Altitude in metres of `pressure` with `qnh` as the sea level reference, both in Pa.
+pub fn altitude(pressure: f32, qnh: f32) -> f32 {
+    SCALE_HEIGHT * (1.0 - f32::powf(pressure / qnh, EXPONENT))
+}
+
+/// Altitude against the standard 1013.25 hPa.
+pub fn pressure_altitude(pressure: f32) -> f32 {
+    altitude(pressure, STANDARD_PRESSURE)
+}
+
+/// Reduce field pressure at `elevation` metres to sea level.
+pub fn qnh_from_qfe(qfe: f32, elevation: f32) -> f32 {
+    qfe / f32::powf(1.0 - elevation / SCALE_HEIGHT, 1.0 / EXPONENT)
+}
+
+/// Field pressure at `elevation` metres for a given sea level pressure.
+pub fn qfe_from_qnh(qnh: f32, elevation: f32) -> f32 {
+    qnh * f32::powf(1.0 - elevation / SCALE_HEIGHT, 1.0 / EXPONENT)
+}
+
+/// Density of moist air in kg/m3. Pa, celsius, percent relative humidity.
+pub fn air_density(pressure: f32, celsius: f32, humidity: f32) -> f32 {
+    let kelvin = celsius + 273.15;
+    let vapor = vapor_pressure(celsius, humidity) * 100.0;
+    (pressure - vapor) / (R_DRY * kelvin) + vapor / (R_VAPOR * kelvin)
+}
+
+/// Altitude in the standard atmosphere with the same air density.
+pub fn density_altitude(pressure: f32, celsius: f32, humidity: f32) -> f32 {
+    let density = air_density(pressure, celsius, humidity);
+    (44.3308 - 42.2665 * f32::powf(density, 0.234_969)) * 1000.0
+}
+
+/// Reads altitude against a configurable QNH.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Altimeter {
+    /// Pa.
+    pub qnh: f32,
+}
+
+impl Altimeter {
+    pub fn new(qnh: f32) -> Altimeter {
+        Altimeter { qnh }
+    }
+
+    /// Set QNH so the current pressure reads a known elevation.
+    pub fn set_elevation(&mut self, pressure: f32, elevation: f32) {
+        self.qnh = qnh_from_qfe(pressure, elevation);
+    }
+
+    pub fn altitude(&self, pressure: f32) -> f32 {
+        altitude(pressure, self.qnh)
+    }
+
+    /// Height above a field whose QFE is known.
+    pub fn height(&self, pressure: f32, qfe: f32) -> f32 {
+        altitude(pressure, qfe)
+    }
+}
+
+impl Default for Altimeter {
+    fn default() -> Altimeter {
+        Altimeter::new(STANDARD_PRESSURE)
+    }
+}
+
+/// Vertical speed from successive altitudes. Both altitude and speed are smoothed.
+pub struct Variometer {
+    altitude: Iir,
+    speed: Iir,
+    last: Option<(f32, Instant)>,
+}
+
+impl Variometer {
+    /// `alpha` is the weight of each new sample; lower is smoother and slower.
+    pub fn new(alpha: f32) -> Variometer {
+        Variometer {
+            altitude: Iir::new(alpha),
+            speed: Iir::new(alpha),
+            last: None,
+        }
+    }
+
+    /// Feed an altitude taken at `at`, return metres per second, positive climbing.
+    pub fn update(&mut self, altitude: f32, at: Instant) -> f32 {
+        let altitude = self.altitude.update(altitude);
+        let speed = match self.last {
+            Some((last_altitude, last_at)) if at > last_at => {
+                let rate = (altitude - last_altitude) / (at - last_at).as_secs_f32();
+                self.speed.update(rate)
+            }
+            _ => self.speed.value().unwrap_or(0.0),
+        };
+        self.last = Some((altitude, at));
+        speed
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trend {
+    Climbing,
+    Level,
+    Sinking,
+}
+
+impl Trend {
+    /// Speeds within `threshold` m/s of zero count as level.
+    pub fn from_speed(speed: f32, threshold: f32) -> Trend {
+        if speed > threshold {
+            Trend::Climbing
+        } else if speed < -threshold {
+            Trend::Sinking
+        } else {
+            Trend::Level
+        }
+    }
+
+    /// Arrow for the LCD.
+    pub fn symbol(&self) -> char {
+        match self {
+            Trend::Climbing => '^',
+            Trend::Level => '-',
+            Trend::Sinking => 'v',
+        }
+    }
+}
+
+/// Active buzzer. Short beeps, quicker the faster we climb; one long tone when sinking.
+pub struct Buzzer {
+    out: gpio::sysfs::SysFsGpioOutput,
+}
+
+impl Buzzer {
+    pub fn new(pin: u16) -> Buzzer {
+        let mut out = gpio::sysfs::SysFsGpioOutput::open(pin).expect("Pin should be active");
+        out.set_value(false).expect("Pin should set");
+        Self { out }
+    }
+
+    fn tone(&mut self, on: Duration, off: Duration) {
+        self.out.set_value(true).expect("Pin should set");
+        thread::sleep(on);
+        self.out.set_value(false).expect("Pin should set");
+        thread::sleep(off);
+    }
+
+    /// Sound roughly `period` worth of indication.
+    pub fn sound(&mut self, trend: Trend, speed: f32, period: Duration) {
+        match trend {
+            Trend::Climbing => {
+                let beeps = (speed.abs().ceil() as u32).clamp(1, 5);
+                let slot = period / beeps;
+                for _ in 0..beeps {
+                    self.tone(slot / 3, slot - slot / 3);
+                }
+            }
+            Trend::Sinking => self.tone(period / 2, period / 2),
+            Trend::Level => thread::sleep(period),
+        }
+    }
+}
+
+/// Show altitude and vertical speed on the LCD against the configured QNH, optionally with
+/// the buzzer.
+pub fn do_variometer(config: &Config, audible: bool) {
+    let mut lcd = LCD::new();
+    lcd.display_init();
+    if !config.backlight {
+        lcd.backlight_off();
+    }
+    let mut buzzer = match audible {
+        true => Some(Buzzer::new(BUZZER_PIN)),
+        false => None,
+    };
+    let filter = Filter {
+        samples: 4,
+        max_deviation: 3.0,
+        alpha: 1.0,
+    };
+    let mut sensor = Filtered::new(detect().expect("Pressure sensor should be found"), filter);
+    let altimeter = Altimeter::new(config.qnh);
+    let mut variometer = Variometer::new(0.3);
+    let period = Duration::from_millis(500);
+
+    loop {
+        let measurement = sensor.measure();
+        let altitude = altimeter.altitude(measurement.pressure);
+        let speed = variometer.update(altitude, Instant::now());
+        let trend = Trend::from_speed(speed, 0.2);
+        lcd.display_data(Vec::from([
+            format!("Alt {:.1} m", altitude),
+            format!("{} {:+.1} m/s", trend.symbol(), speed),
+        ]));
+        match buzzer.as_mut() {
+            Some(buzzer) => buzzer.sound(trend, speed, period),
+            None => thread::sleep(period),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::assert_near;
+
+    #[test]
+    fn test_standard_atmosphere() {
+        assert_near(pressure_altitude(STANDARD_PRESSURE), 0.0, 0.01);
+        // ISA table: 1000 m is 898.76 hPa.
+        assert_near(pressure_altitude(89_876.0), 1000.0, 1.0);
+    }
+
+    #[test]
+    fn test_qnh_qfe_round_trip() {
+        let qnh = qnh_from_qfe(95_000.0, 540.0);
+        assert_near(qfe_from_qnh(qnh, 540.0), 95_000.0, 0.5);
+        let mut altimeter = Altimeter::default();
+        altimeter.set_elevation(95_000.0, 540.0);
+        assert_near(altimeter.altitude(95_000.0), 540.0, 0.1);
+    }
+
+    #[test]
+    fn test_density_altitude() {
+        // Dry standard day at sea level.
+        assert_near(density_altitude(STANDARD_PRESSURE, 15.0, 0.0), 0.0, 10.0);
+        // Hot and humid at sea level reads well above it.
+        assert!(density_altitude(STANDARD_PRESSURE, 35.0, 80.0) > 700.0);
+    }
+
+    #[test]
+    fn test_variometer_steady_climb() {
+        let start = Instant::now();
+        let mut variometer = Variometer::new(1.0);
+        let mut speed = 0.0;
+        for i in 0..10 {
+            let at = start + Duration::from_secs(i);
+            speed = variometer.update(2.0 * i as f32, at);
+        }
+        assert_near(speed, 2.0, 0.001);
+        assert_eq!(Trend::from_speed(speed, 0.2), Trend::Climbing);
+    }
+}
@@ -0,0 +1,81 @@
+// Inputs, errors and the trait shared by the 8 bit ADC drivers.
+
+use std::fmt;
+
+const FULL_SCALE: f32 = 255.0;
+
+/// Multiplexer setting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    /// A channel against ground.
+    SingleEnded(u8),
+    /// A channel against the other one of its pair (0/1, 2/3, ...), which is the negative input.
+    Differential(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdcError {
+    /// The part has no such channel.
+    BadChannel { channel: u8, channels: u8 },
+    /// The MSB first and LSB first copies of a conversion differ (ADC0832 family).
+    Mismatch { msb_first: u8, lsb_first: u8 },
+}
+
+impl fmt::Display for AdcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AdcError::BadChannel { channel, channels } => {
+                write!(f, "channel {channel} out of range, part has {channels}")
+            }
+            AdcError::Mismatch {
+                msb_first,
+                lsb_first,
+            } => write!(
+                f,
+                "conversion read {msb_first:#04x} MSB first but {lsb_first:#04x} LSB first"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AdcError {}
+
+/// Volts for a raw count.
+pub fn voltage(raw: u8, vref: f32) -> f32 {
+    raw as f32 * vref / FULL_SCALE
+}
+
+/// What every 8 bit analog to digital converter can do.
+///
+/// A differential input reads 0 when the negative side is higher, as on the ADC0832.
+pub trait AnalogInput {
+    /// Raw count, 0 to 255 of `vref`.
+    fn read(&mut self, input: Input) -> Result<u8, AdcError>;
+
+    fn vref(&self) -> f32;
+
+    fn read_voltage(&mut self, input: Input) -> Result<f32, AdcError> {
+        Ok(voltage(self.read(input)?, self.vref()))
+    }
+}
+
+impl<A: AnalogInput + ?Sized> AnalogInput for Box<A> {
+    fn read(&mut self, input: Input) -> Result<u8, AdcError> {
+        (**self).read(input)
+    }
+
+    fn vref(&self) -> f32 {
+        (**self).vref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voltage() {
+        assert_eq!(voltage(0, 3.3), 0.0);
+        assert_eq!(voltage(255, 5.0), 5.0);
+    }
+}
@@ -1,15 +1,129 @@
 // Datasheet BMP085:
 // https://www.sparkfun.com/datasheets/Components/General/BST-BMP085-DS000-05.pdf
+//
+// BMP280 and BME280 live in bme280.rs; `detect` picks whichever is fitted.
 
 extern crate i2c_linux;
 
+use crate::bme280::Bme280;
+use gpio::sysfs::SysFsGpioInput;
+use gpio::GpioIn;
+use gpio::GpioValue::High;
 use i2c_linux::I2c;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs::File;
+use std::sync::mpsc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 const SEA_LEVEL_PA: f32 = 101_325.0;
 
+// Chip ID register, common to the whole family.
+const CHIP_ID: u8 = 0xD0;
+
+// BMP085 only answers on 0x77; the BMx280 on either.
+const ADDRESSES: [u16; 2] = [0x77, 0x76];
+
+// First calibration word. AC1 through MD follow, two bytes each.
+const EEPROM_START: u8 = 0xAA;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BarometerError {
+    /// Nothing from the family answered on either address.
+    NotFound,
+    /// A calibration word read as all zeros or all ones; usually a loose I2C connection.
+    BadCalibration { register: u8, value: u16 },
+}
+
+impl fmt::Display for BarometerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BarometerError::NotFound => write!(f, "no pressure sensor found"),
+            BarometerError::BadCalibration { register, value } => write!(
+                f,
+                "calibration register {register:#04x} read {value:#06x}; check the I2C wiring"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BarometerError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chip {
+    /// BMP085 and BMP180.
+    Bmp085,
+    Bmp280,
+    /// BMP280 with a humidity sensor.
+    Bme280,
+}
+
+impl Chip {
+    pub fn from_id(id: u8) -> Option<Chip> {
+        match id {
+            0x55 => Some(Chip::Bmp085),
+            0x56..=0x58 => Some(Chip::Bmp280),
+            0x60 => Some(Chip::Bme280),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Measurement {
+    pub celsius: f32,
+    /// Pascal.
+    pub pressure: f32,
+    /// Percent relative humidity, BME280 only.
+    pub humidity: Option<f32>,
+}
+
+/// What every supported pressure sensor can do.
+pub trait PressureSensor {
+    fn chip(&self) -> Chip;
+
+    /// Read temperature, pressure and, when the chip has it, humidity.
+    fn measure(&mut self) -> Measurement;
+}
+
+impl<S: PressureSensor + ?Sized> PressureSensor for Box<S> {
+    fn chip(&self) -> Chip {
+        (**self).chip()
+    }
+
+    fn measure(&mut self) -> Measurement {
+        (**self).measure()
+    }
+}
+
+/// Probe the chip ID register on both addresses and return an initialised driver.
+pub fn detect() -> Result<Box<dyn PressureSensor + Send>, BarometerError> {
+    let mut i2c = I2c::from_path("/dev/i2c-1").expect("Device should be found");
+    for addr in ADDRESSES {
+        if i2c.smbus_set_slave_address(addr, false).is_err() {
+            continue;
+        }
+        let chip = match i2c.smbus_read_byte_data(CHIP_ID).map(Chip::from_id) {
+            Ok(Some(chip)) => chip,
+            _ => continue,
+        };
+        return Ok(match chip {
+            Chip::Bmp085 => {
+                let mut barometer = Barometer::new();
+                barometer.init()?;
+                Box::new(barometer)
+            }
+            Chip::Bmp280 | Chip::Bme280 => {
+                let mut bme280 = Bme280::new(addr, chip);
+                bme280.init()?;
+                Box::new(bme280)
+            }
+        });
+    }
+    Err(BarometerError::NotFound)
+}
+
 pub struct Barometer {
     // Device.
     i2c: I2c<File>,
@@ -42,24 +156,21 @@ pub struct Barometer {
     cal_mc: u8,
     cal_md: u8,
 
-    // Registers
-    ac1: i16,
-    ac2: i16,
-    ac3: i16,
-    ac4: u16,
-    ac5: u16,
-    ac6: u16,
-    b1: i16,
-    b2: i16,
-    b5: i64,
-    mb: i16,
-    mc: i16,
-    md: i16,
+    // Calibration values, read by `init`.
+    calibration: Calibration,
+
     // Commands.
     read_temp: u8,
     read_pressure: u8,
+
+    // Used by `measure`.
+    mode: Mode,
+
+    // End of conversion; high once a result is ready. Fixed sleeps when not wired.
+    eoc: Option<SysFsGpioInput>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Mode {
     LowPower,
     Standard,
@@ -67,6 +178,179 @@ pub enum Mode {
     UltraHighRes,
 }
 
+/// Factory calibration words from the EEPROM.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub ac1: i16,
+    pub ac2: i16,
+    pub ac3: i16,
+    pub ac4: u16,
+    pub ac5: u16,
+    pub ac6: u16,
+    pub b1: i16,
+    pub b2: i16,
+    pub mb: i16,
+    pub mc: i16,
+    pub md: i16,
+}
+
+/// A compensated reading.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Compensated {
+    /// Tenths of a degree celsius.
+    pub temperature: i64,
+    /// Pascal.
+    pub pressure: i64,
+}
+
+/// Everything needed to reproduce a reading off the device, for bug reports.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dump {
+    /// EEPROM words AC1 through MD, as read.
+    pub eeprom: [u16; 11],
+    pub oss: u8,
+    /// UT.
+    pub raw_temp: i64,
+    /// UP.
+    pub raw_pressure: i64,
+    /// What the device compensated them to, if the EEPROM was valid.
+    pub compensated: Option<Compensated>,
+    /// Why the EEPROM was rejected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Calibration {
+    /// Build from the raw EEPROM words, rejecting any that read 0x0000 or 0xFFFF.
+    pub fn from_eeprom(words: [u16; 11]) -> Result<Calibration, BarometerError> {
+        for (i, &value) in words.iter().enumerate() {
+            if value == 0x0000 || value == 0xFFFF {
+                return Err(BarometerError::BadCalibration {
+                    register: EEPROM_START + 2 * i as u8,
+                    value,
+                });
+            }
+        }
+        Ok(Calibration {
+            ac1: words[0] as i16,
+            ac2: words[1] as i16,
+            ac3: words[2] as i16,
+            ac4: words[3],
+            ac5: words[4],
+            ac6: words[5],
+            b1: words[6] as i16,
+            b2: words[7] as i16,
+            mb: words[8] as i16,
+            mc: words[9] as i16,
+            md: words[10] as i16,
+        })
+    }
+}
+
+impl Dump {
+    /// Run the recorded raw values back through compensation.
+    pub fn replay(&self) -> Result<Compensated, BarometerError> {
+        let calibration = Calibration::from_eeprom(self.eeprom)?;
+        Ok(compensate_pressure(
+            &calibration,
+            self.raw_temp,
+            self.raw_pressure,
+            self.oss,
+        ))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Dump should serialize")
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Dump> {
+        serde_json::from_str(json)
+    }
+}
+
+impl Mode {
+    /// Oversampling setting, 0 through 3.
+    pub fn oss(&self) -> u8 {
+        match self {
+            Mode::LowPower => 0,
+            Mode::Standard => 1,
+            Mode::HighRes => 2,
+            Mode::UltraHighRes => 3,
+        }
+    }
+
+    /// Longest a pressure conversion takes, rounded up from the datasheet.
+    pub fn conversion_time(&self) -> Duration {
+        match self {
+            Mode::LowPower => Duration::from_millis(5),
+            Mode::Standard => Duration::from_millis(8),
+            Mode::HighRes => Duration::from_millis(14),
+            Mode::UltraHighRes => Duration::from_millis(26),
+        }
+    }
+}
+
+/// A timestamped reading from `Barometer::sample_continuously`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sample {
+    pub at: Instant,
+    pub compensated: Compensated,
+}
+
+// Compensation from the datasheet, section 3.5. Pure so it can be checked against the worked
+// example and replayed from recorded raw values.
+
+/// Return tenths of a degree celsius and B5, which pressure compensation needs.
+pub fn compensate_temperature(cal: &Calibration, raw_temp: i64) -> (i64, i64) {
+    let x1 = ((raw_temp - cal.ac6 as i64) * cal.ac5 as i64) >> 15;
+    // Truncating division as in Bosch's reference code. A blank calibration would divide by
+    // zero; treat it as no correction rather than panic.
+    let x2 = ((cal.mc as i64) << 11)
+        .checked_div(x1 + cal.md as i64)
+        .unwrap_or(0);
+    let b5 = x1 + x2;
+    ((b5 + 8) >> 4, b5)
+}
+
+/// Compensate a raw pressure taken at oversampling `oss`, along with its raw temperature.
+pub fn compensate_pressure(
+    cal: &Calibration,
+    raw_temp: i64,
+    raw_pressure: i64,
+    oss: u8,
+) -> Compensated {
+    let (temperature, b5) = compensate_temperature(cal, raw_temp);
+    let b6 = b5 - 4000;
+    let x1 = (cal.b2 as i64 * ((b6 * b6) >> 12)) >> 11;
+    let x2 = (cal.ac2 as i64 * b6) >> 11;
+    let x3 = x1 + x2;
+    let b3 = (((cal.ac1 as i64 * 4 + x3) << oss) + 2) / 4;
+    let x1 = (cal.ac3 as i64 * b6) >> 13;
+    let x2 = (cal.b1 as i64 * ((b6 * b6) >> 12)) >> 16;
+    let x3 = ((x1 + x2) + 2) >> 2;
+    let b4 = (cal.ac4 as i64 * (x3 + 32_768)) >> 15;
+    // Signed, so a raw value below B3 gives a low pressure rather than wrapping.
+    let b7 = (raw_pressure - b3) * (50_000 >> oss);
+    if b4 == 0 {
+        return Compensated {
+            temperature,
+            pressure: 0,
+        };
+    }
+    let mut pressure = match b7 < 0x8000_0000 {
+        true => (b7 * 2) / b4,
+        false => (b7 / b4) * 2,
+    };
+    let mut x1 = (pressure >> 8) * (pressure >> 8);
+    x1 = (x1 * 3038) >> 16;
+    let x2 = (-7357 * pressure) >> 16;
+    pressure += (x1 + x2 + 3791) >> 4;
+    Compensated {
+        temperature,
+        pressure,
+    }
+}
+
 impl Barometer {
     pub fn new() -> Barometer {
         let i2c = I2c::from_path("/dev/i2c-1".to_string()).expect("Device should be found");
@@ -90,20 +374,10 @@ impl Barometer {
         let cal_mb = 0xBA_u8;
         let cal_mc = 0xBC_u8;
         let cal_md = 0xBE_u8;
-        let ac1 = 0_i16;
-        let ac2 = 0_i16;
-        let ac3 = 0_i16;
-        let ac4 = 0_u16;
-        let ac5 = 0_u16;
-        let ac6 = 0_u16;
-        let b1 = 0_i16;
-        let b2 = 0_i16;
-        let b5 = 0_i64;
-        let mb = 0_i16;
-        let mc = 0_i16;
-        let md = 0_i16;
+        let calibration = Calibration::default();
         let read_temp = 0x2E_u8;
         let read_pressure = 0x34_u8;
+        let mode = Mode::HighRes;
 
         Self {
             i2c,
@@ -127,20 +401,39 @@ impl Barometer {
             cal_mb,
             cal_mc,
             cal_md,
-            ac1,
-            ac2,
-            ac3,
-            ac4,
-            ac5,
-            ac6,
-            b1,
-            b2,
-            b5,
-            mb,
-            mc,
-            md,
+            calibration,
             read_temp,
             read_pressure,
+            mode,
+            eoc: None,
+        }
+    }
+
+    /// Oversampling used by `measure`. Defaults to HighRes.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    /// Wait on the EOC pin rather than sleeping for the worst case conversion time.
+    pub fn set_eoc_pin(&mut self, pin: u16) {
+        self.eoc = Some(SysFsGpioInput::open(pin).expect("Pin should be active"));
+    }
+
+    /// Return once a conversion started just now has finished.
+    ///
+    /// With EOC wired, poll it; `max` is then only a timeout, after which the result is
+    /// ready per the datasheet anyway.
+    fn wait_for_conversion(&mut self, max: Duration) {
+        match self.eoc.as_mut() {
+            Some(eoc) => {
+                let start = Instant::now();
+                while eoc.read_value().expect("Pin should read") != High {
+                    if start.elapsed() > max {
+                        break;
+                    }
+                }
+            }
+            None => thread::sleep(max),
         }
     }
 
@@ -156,39 +449,69 @@ impl Barometer {
         data
     }
 
-    fn read_s16(&mut self, command: u8) -> i16 {
-        let raw_read = self.read_u16(command);
-        return match raw_read > 32_767 {
-            true => (raw_read as i32 - 65_536_i32) as i16,
-            false => raw_read as i16,
-        };
+    /// Calibration words AC1 through MD, unchecked.
+    pub fn read_eeprom(&mut self) -> [u16; 11] {
+        [
+            self.read_u16(self.cal_ac1),
+            self.read_u16(self.cal_ac2),
+            self.read_u16(self.cal_ac3),
+            self.read_u16(self.cal_ac4),
+            self.read_u16(self.cal_ac5),
+            self.read_u16(self.cal_ac6),
+            self.read_u16(self.cal_b1),
+            self.read_u16(self.cal_b2),
+            self.read_u16(self.cal_mb),
+            self.read_u16(self.cal_mc),
+            self.read_u16(self.cal_md),
+        ]
     }
 
-    pub fn init(&mut self) {
+    fn select(&mut self) {
         self.i2c
             .smbus_set_slave_address(self.addr, false)
             .expect("Slave addr should be set");
-        // Calibration
-        for i in 0..1 {
-            self.ac1 = self.read_s16(self.cal_ac1 + i);
-            self.ac2 = self.read_s16(self.cal_ac2 + i);
-            self.ac3 = self.read_s16(self.cal_ac3 + i);
-            self.ac4 = self.read_u16(self.cal_ac4 + i);
-            self.ac5 = self.read_u16(self.cal_ac5 + i);
-            self.ac6 = self.read_u16(self.cal_ac6 + i);
-            self.b1 = self.read_s16(self.cal_b1 + i);
-            self.b2 = self.read_s16(self.cal_b2 + i);
-            self.mb = self.read_s16(self.cal_mb + i);
-            self.mc = self.read_s16(self.cal_mc + i);
-            self.md = self.read_s16(self.cal_md + i);
+    }
+
+    /// Read and validate the calibration.
+    pub fn init(&mut self) -> Result<(), BarometerError> {
+        self.select();
+        let words = self.read_eeprom();
+        self.calibration = Calibration::from_eeprom(words)?;
+        Ok(())
+    }
+
+    /// Record the EEPROM as read and one raw reading at the current mode.
+    ///
+    /// Needs no `init`; a bad EEPROM is reported in the dump instead of compensated values.
+    pub fn dump(&mut self) -> Dump {
+        self.select();
+        let mode = self.mode;
+        let eeprom = self.read_eeprom();
+        let raw_temp = self.read_raw_temp();
+        let raw_pressure = self.read_raw_pressure(&mode);
+        let calibration = Calibration::from_eeprom(eeprom);
+        Dump {
+            eeprom,
+            oss: mode.oss(),
+            raw_temp,
+            raw_pressure,
+            compensated: calibration
+                .as_ref()
+                .ok()
+                .map(|cal| compensate_pressure(cal, raw_temp, raw_pressure, mode.oss())),
+            error: calibration.err().map(|e| e.to_string()),
         }
     }
 
+    pub fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+
     pub fn read_raw_temp(&mut self) -> i64 {
         self.i2c
             .smbus_write_byte_data(self.control, self.read_temp & 0xFF)
             .expect("data should write");
-        thread::sleep(Duration::from_millis(5));
+        self.wait_for_conversion(Duration::from_millis(5));
         let msb = match self.i2c.smbus_read_byte_data(self.msb) {
             Ok(msb) => msb & 0xFF,
             Err(_e) => panic!(),
@@ -200,13 +523,9 @@ impl Barometer {
         ((msb as i64) << 8) + lsb as i64
     }
 
-    pub fn read_temperature(&mut self, raw_temp: i64) -> i64 {
-        // From datasheet
-        let x1: i64 = ((raw_temp - self.ac6 as i64) * self.ac5 as i64) >> 15;
-        let x2: i64 = ((self.mc as i64) << 11) / (x1 + self.md as i64);
-        let b5 = x1 + x2;
-        self.b5 = x1 + x2;
-        (b5 + 8) >> 4
+    /// Tenths of a degree celsius.
+    pub fn read_temperature(&self, raw_temp: i64) -> i64 {
+        compensate_temperature(&self.calibration, raw_temp).0
     }
 
     pub fn read_raw_pressure(&mut self, mode: &Mode) -> i64 {
@@ -219,7 +538,7 @@ impl Barometer {
                         self.read_pressure + (self.low_power_mask << 6) & 0xFF,
                     )
                     .expect("should write");
-                thread::sleep(Duration::from_millis(5));
+                self.wait_for_conversion(Mode::LowPower.conversion_time());
                 raw_modifier = self.low_power_mask;
             }
             Mode::Standard => {
@@ -229,7 +548,7 @@ impl Barometer {
                         self.read_pressure + (self.standard_res_mask << 6) & 0xFF,
                     )
                     .expect("should write");
-                thread::sleep(Duration::from_millis(8));
+                self.wait_for_conversion(Mode::Standard.conversion_time());
                 raw_modifier = self.standard_res_mask;
             }
             Mode::HighRes => {
@@ -239,7 +558,7 @@ impl Barometer {
                         self.read_pressure + (self.high_res_mask << 6) & 0xFF,
                     )
                     .expect("should write");
-                thread::sleep(Duration::from_millis(14));
+                self.wait_for_conversion(Mode::HighRes.conversion_time());
                 raw_modifier = self.high_res_mask;
             }
             Mode::UltraHighRes => {
@@ -249,7 +568,7 @@ impl Barometer {
                         self.read_pressure + (self.ultra_high_res_mask << 6) & 0xFF,
                     )
                     .expect("should write");
-                thread::sleep(Duration::from_millis(26));
+                self.wait_for_conversion(Mode::UltraHighRes.conversion_time());
                 raw_modifier = self.ultra_high_res_mask;
             }
         }
@@ -268,51 +587,187 @@ impl Barometer {
         (((msb as i64) << 16) + ((lsb as i64) << 8) + xlsb as i64) >> (8 - raw_modifier)
     }
 
-    pub fn read_pressure(&self, raw_pressure: i64, mode: &Mode) -> i64 {
-        // From datasheet.
-        let b6: i64 = self.b5 - 4000;
-        let x1: i64 = (self.b2 as i64 * (b6 * (b6 >> 12))) >> 11;
-        let x2: i64 = (self.ac2 as i64 * b6) >> 12;
-        let x3: i64 = x1 + x2;
-        let b3: i64 = match mode {
-            Mode::LowPower => (((self.ac1 as i64 * 4 + x3) << self.low_power_mask) + 2) / 4,
-            Mode::Standard => (((self.ac1 as i64 * 4 + x3) << self.standard_res_mask) + 2) / 4,
-            Mode::HighRes => (((self.ac1 as i64 * 4 + x3) << self.high_res_mask) + 2) / 4,
-            Mode::UltraHighRes => {
-                (((self.ac1 as i64 * 4 + x3) << self.ultra_high_res_mask) + 2) / 4
-            }
-        };
-        let z1: i64 = (self.ac3 as i64 * b6) >> 13;
-        let z2: i64 = (self.b1 as i64 * (b6 * (b6 >> 12))) >> 16;
-        let z3: i64 = ((z1 + z2) + 2) >> 2;
-        let b4: u64 = self.ac4 as u64 * (z3 as u64 + 32_768) >> 15;
-        let b7: u64 = match mode {
-            Mode::LowPower => (raw_pressure - b3) * (50_000 >> self.low_power_mask),
-            Mode::Standard => (raw_pressure - b3) * (50_000 >> self.standard_res_mask),
-            Mode::HighRes => (raw_pressure - b3) * (50_000 >> self.high_res_mask),
-            Mode::UltraHighRes => (raw_pressure - b3) * (50_000 >> self.ultra_high_res_mask),
-        } as u64;
-        let mut pressure: i64 = match b7 < 0x80_000_000 {
-            true => (b7 * 2) / b4,
-            false => (b7 / b4) * 2,
-        } as i64;
-        let mut final1 = (pressure >> 8) * (pressure >> 8);
-        final1 = (final1 * 3038) >> 16;
-        let final2 = (-7357 * pressure) >> 16;
-
-        pressure = pressure + ((final1 + final2 + 3791) >> 4);
-        pressure
+    /// Pascal. Needs the raw temperature taken alongside the raw pressure.
+    pub fn read_pressure(&self, raw_temp: i64, raw_pressure: i64, mode: &Mode) -> i64 {
+        compensate_pressure(&self.calibration, raw_temp, raw_pressure, mode.oss()).pressure
+    }
+
+    /// Take raw temperature and pressure and compensate them together.
+    pub fn read_compensated(&mut self, mode: &Mode) -> Compensated {
+        let raw_temp = self.read_raw_temp();
+        let raw_pressure = self.read_raw_pressure(mode);
+        compensate_pressure(&self.calibration, raw_temp, raw_pressure, mode.oss())
+    }
+
+    /// Sample pressure back to back on a background thread, refreshing temperature every
+    /// `temp_every` samples since it changes slowly.
+    ///
+    /// Best with EOC wired. Runs until the receiver is dropped.
+    pub fn sample_continuously(mut self, mode: Mode, temp_every: u32) -> mpsc::Receiver<Sample> {
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("barometer".to_string())
+            .spawn(move || {
+                let mut raw_temp = self.read_raw_temp();
+                let mut count = 0;
+                loop {
+                    if count == temp_every.max(1) {
+                        raw_temp = self.read_raw_temp();
+                        count = 0;
+                    }
+                    let raw_pressure = self.read_raw_pressure(&mode);
+                    count += 1;
+                    let sample = Sample {
+                        at: Instant::now(),
+                        compensated: compensate_pressure(
+                            &self.calibration,
+                            raw_temp,
+                            raw_pressure,
+                            mode.oss(),
+                        ),
+                    };
+                    if sender.send(sample).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("Thread should exist");
+        receiver
     }
 
     pub fn read_altitude(&mut self, mode: &Mode) -> f32 {
-        let raw_pressure: i64 = self.read_raw_pressure(&mode);
-        let pressure: i64 = self.read_pressure(raw_pressure, &mode);
+        let pressure: i64 = self.read_compensated(mode).pressure;
         44330.0_f32 * (1.0 - f32::powf(pressure as f32 / SEA_LEVEL_PA, 1.0 / 5.255))
     }
 
     pub fn read_sea_level_pressure(&mut self, mode: &Mode, altitude: f32) -> f32 {
-        let raw_pressure: i64 = self.read_raw_pressure(&mode);
-        let pressure: i64 = self.read_pressure(raw_pressure, &mode);
+        let pressure: i64 = self.read_compensated(mode).pressure;
         pressure as f32 / f32::powf(1.0 - altitude / 44330.0_f32, 5.255)
     }
 }
+
+impl PressureSensor for Barometer {
+    fn chip(&self) -> Chip {
+        Chip::Bmp085
+    }
+
+    fn measure(&mut self) -> Measurement {
+        let mode = self.mode;
+        let compensated = self.read_compensated(&mode);
+        Measurement {
+            celsius: compensated.temperature as f32 / 10.0,
+            pressure: compensated.pressure as f32,
+            humidity: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Worked example from the datasheet, section 3.5.
+    const DATASHEET_CALIBRATION: Calibration = Calibration {
+        ac1: 408,
+        ac2: -72,
+        ac3: -14383,
+        ac4: 32741,
+        ac5: 32757,
+        ac6: 23153,
+        b1: 6190,
+        b2: 4,
+        mb: -32768,
+        mc: -8711,
+        md: 2868,
+    };
+
+    #[test]
+    fn test_compensate_temperature() {
+        // The datasheet rounds X2 to -2344 and gets B5 = 2399; the reference code truncates
+        // to -2343.
+        assert_eq!(
+            compensate_temperature(&DATASHEET_CALIBRATION, 27898),
+            (150, 2400)
+        );
+    }
+
+    #[test]
+    fn test_blank_calibration_does_not_divide_by_zero() {
+        let blank = Calibration::default();
+        assert_eq!(compensate_temperature(&blank, 0), (0, 0));
+        assert_eq!(compensate_pressure(&blank, 27898, 23843, 0).pressure, 0);
+    }
+
+    #[test]
+    fn test_compensate_pressure() {
+        assert_eq!(
+            compensate_pressure(&DATASHEET_CALIBRATION, 27898, 23843, Mode::LowPower.oss()),
+            Compensated {
+                temperature: 150,
+                pressure: 69964
+            }
+        );
+    }
+
+    #[test]
+    fn test_calibration_rejects_blank_words() {
+        let mut words = [0x1234_u16; 11];
+        assert!(Calibration::from_eeprom(words).is_ok());
+        words[3] = 0xFFFF;
+        assert_eq!(
+            Calibration::from_eeprom(words),
+            Err(BarometerError::BadCalibration {
+                register: 0xB0,
+                value: 0xFFFF
+            })
+        );
+        assert_eq!(
+            Calibration::from_eeprom([0; 11]),
+            Err(BarometerError::BadCalibration {
+                register: 0xAA,
+                value: 0x0000
+            })
+        );
+    }
+
+    #[test]
+    fn test_dump_replay() {
+        let c = DATASHEET_CALIBRATION;
+        let dump = Dump {
+            eeprom: [
+                c.ac1 as u16,
+                c.ac2 as u16,
+                c.ac3 as u16,
+                c.ac4,
+                c.ac5,
+                c.ac6,
+                c.b1 as u16,
+                c.b2 as u16,
+                c.mb as u16,
+                c.mc as u16,
+                c.md as u16,
+            ],
+            oss: 0,
+            raw_temp: 27898,
+            raw_pressure: 23843,
+            compensated: Some(Compensated {
+                temperature: 150,
+                pressure: 69964,
+            }),
+            error: None,
+        };
+        let replayed = Dump::from_json(&dump.to_json()).unwrap();
+        assert_eq!(replayed, dump);
+        assert_eq!(
+            Calibration::from_eeprom(replayed.eeprom),
+            Ok(DATASHEET_CALIBRATION)
+        );
+        assert_eq!(replayed.replay().ok(), dump.compensated);
+    }
+
+    #[test]
+    fn test_raw_pressure_below_b3_stays_signed() {
+        let compensated = compensate_pressure(&DATASHEET_CALIBRATION, 27898, 0, 0);
+        assert!(compensated.pressure < 0);
+    }
+}
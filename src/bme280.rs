@@ -0,0 +1,358 @@
+// Datasheet BMP280:
+// https://www.bosch-sensortec.com/media/boschsensortec/downloads/datasheets/bst-bmp280-ds001.pdf
+// Datasheet BME280:
+// https://www.bosch-sensortec.com/media/boschsensortec/downloads/datasheets/bst-bme280-ds002.pdf
+
+extern crate i2c_linux;
+
+use crate::barometer::{BarometerError, Chip, Measurement, PressureSensor};
+use i2c_linux::I2c;
+use std::fs::File;
+use std::thread;
+use std::time::Duration;
+
+// Registers.
+const CALIB_TP: u8 = 0x88; // dig_T1 through dig_P9, little endian words.
+const CALIB_H1: u8 = 0xA1;
+const CALIB_H2: u8 = 0xE1; // dig_H2 through dig_H6.
+const RESET: u8 = 0xE0;
+const CTRL_HUM: u8 = 0xF2;
+const STATUS: u8 = 0xF3;
+const CTRL_MEAS: u8 = 0xF4;
+const CONFIG: u8 = 0xF5;
+const DATA: u8 = 0xF7; // press msb, lsb, xlsb, temp msb, lsb, xlsb, hum msb, lsb.
+
+// Commands.
+const SOFT_RESET: u8 = 0xB6;
+const FORCED_MODE: u8 = 0x01;
+const MEASURING: u8 = 0x08;
+
+// Oversampling; datasheet's "standard resolution" setting.
+const OSRS_T: u8 = 0x01; // x1
+const OSRS_P: u8 = 0x03; // x4
+const OSRS_H: u8 = 0x01; // x1
+
+/// Trimming parameters burned into each chip. Humidity words are zero on a BMP280.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Calibration {
+    pub dig_t1: u16,
+    pub dig_t2: i16,
+    pub dig_t3: i16,
+    pub dig_p1: u16,
+    pub dig_p2: i16,
+    pub dig_p3: i16,
+    pub dig_p4: i16,
+    pub dig_p5: i16,
+    pub dig_p6: i16,
+    pub dig_p7: i16,
+    pub dig_p8: i16,
+    pub dig_p9: i16,
+    pub dig_h1: u8,
+    pub dig_h2: i16,
+    pub dig_h3: u8,
+    pub dig_h4: i16,
+    pub dig_h5: i16,
+    pub dig_h6: i8,
+}
+
+impl Calibration {
+    /// Reject the words that are never blank on a working chip but read 0x0000 or 0xFFFF
+    /// over a loose connection. Compensating with those gives nonsense.
+    pub fn check(&self, chip: Chip) -> Result<(), BarometerError> {
+        let mut words = Vec::from([(CALIB_TP, self.dig_t1), (CALIB_TP + 6, self.dig_p1)]);
+        if chip == Chip::Bme280 {
+            words.push((CALIB_H2, self.dig_h2 as u16));
+        }
+        match words
+            .iter()
+            .find(|(_, value)| *value == 0x0000 || *value == 0xFFFF)
+        {
+            Some(&(register, value)) => Err(BarometerError::BadCalibration { register, value }),
+            None => Ok(()),
+        }
+    }
+}
+
+pub struct Bme280 {
+    i2c: I2c<File>,
+    addr: u16,
+    chip: Chip,
+    calibration: Calibration,
+}
+
+impl Bme280 {
+    /// `chip` is Bmp280 or Bme280, as read from the chip ID register.
+    pub fn new(addr: u16, chip: Chip) -> Bme280 {
+        let i2c = I2c::from_path("/dev/i2c-1").expect("Device should be found");
+        Self {
+            i2c,
+            addr,
+            chip,
+            calibration: Calibration::default(),
+        }
+    }
+
+    fn read_u8(&mut self, register: u8) -> u8 {
+        self.i2c
+            .smbus_read_byte_data(register)
+            .expect("Register should read")
+    }
+
+    fn read_u16_le(&mut self, register: u8) -> u16 {
+        u16::from_le_bytes([self.read_u8(register), self.read_u8(register + 1)])
+    }
+
+    fn read_i16_le(&mut self, register: u8) -> i16 {
+        self.read_u16_le(register) as i16
+    }
+
+    /// Reset, then read and check the calibration.
+    pub fn init(&mut self) -> Result<(), BarometerError> {
+        self.i2c
+            .smbus_set_slave_address(self.addr, false)
+            .expect("Slave addr should be set");
+        self.i2c
+            .smbus_write_byte_data(RESET, SOFT_RESET)
+            .expect("data should write");
+        thread::sleep(Duration::from_millis(5));
+
+        let mut cal = Calibration {
+            dig_t1: self.read_u16_le(CALIB_TP),
+            dig_t2: self.read_i16_le(CALIB_TP + 2),
+            dig_t3: self.read_i16_le(CALIB_TP + 4),
+            dig_p1: self.read_u16_le(CALIB_TP + 6),
+            dig_p2: self.read_i16_le(CALIB_TP + 8),
+            dig_p3: self.read_i16_le(CALIB_TP + 10),
+            dig_p4: self.read_i16_le(CALIB_TP + 12),
+            dig_p5: self.read_i16_le(CALIB_TP + 14),
+            dig_p6: self.read_i16_le(CALIB_TP + 16),
+            dig_p7: self.read_i16_le(CALIB_TP + 18),
+            dig_p8: self.read_i16_le(CALIB_TP + 20),
+            dig_p9: self.read_i16_le(CALIB_TP + 22),
+            ..Calibration::default()
+        };
+        if self.chip == Chip::Bme280 {
+            cal.dig_h1 = self.read_u8(CALIB_H1);
+            cal.dig_h2 = self.read_i16_le(CALIB_H2);
+            cal.dig_h3 = self.read_u8(CALIB_H2 + 2);
+            // dig_H4 and dig_H5 are 12 bit values sharing the nibbles of 0xE5.
+            let e4 = self.read_u8(CALIB_H2 + 3) as i8 as i16;
+            let e5 = self.read_u8(CALIB_H2 + 4) as i16;
+            let e6 = self.read_u8(CALIB_H2 + 5) as i8 as i16;
+            cal.dig_h4 = (e4 << 4) | (e5 & 0x0F);
+            cal.dig_h5 = (e6 << 4) | (e5 >> 4);
+            cal.dig_h6 = self.read_u8(CALIB_H2 + 6) as i8;
+        }
+        cal.check(self.chip)?;
+        self.calibration = cal;
+
+        // No IIR filter; forced mode takes one fresh sample per measurement.
+        self.i2c
+            .smbus_write_byte_data(CONFIG, 0x00)
+            .expect("data should write");
+        Ok(())
+    }
+
+    /// Trigger a forced measurement and return raw temperature, pressure and humidity.
+    pub fn read_raw(&mut self) -> (i32, i32, Option<i32>) {
+        if self.chip == Chip::Bme280 {
+            // Only latched by the following write to ctrl_meas.
+            self.i2c
+                .smbus_write_byte_data(CTRL_HUM, OSRS_H)
+                .expect("data should write");
+        }
+        self.i2c
+            .smbus_write_byte_data(CTRL_MEAS, (OSRS_T << 5) | (OSRS_P << 2) | FORCED_MODE)
+            .expect("data should write");
+        thread::sleep(Duration::from_millis(10));
+        while self.read_u8(STATUS) & MEASURING != 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let mut data = [0_u8; 8];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = self.read_u8(DATA + i as u8);
+        }
+        let raw_pressure =
+            ((data[0] as i32) << 12) | ((data[1] as i32) << 4) | (data[2] as i32 >> 4);
+        let raw_temp = ((data[3] as i32) << 12) | ((data[4] as i32) << 4) | (data[5] as i32 >> 4);
+        let raw_humidity = match self.chip {
+            Chip::Bme280 => Some(((data[6] as i32) << 8) | data[7] as i32),
+            _ => None,
+        };
+        (raw_temp, raw_pressure, raw_humidity)
+    }
+
+    pub fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+}
+
+impl PressureSensor for Bme280 {
+    fn chip(&self) -> Chip {
+        self.chip
+    }
+
+    fn measure(&mut self) -> Measurement {
+        let (raw_temp, raw_pressure, raw_humidity) = self.read_raw();
+        let cal = self.calibration;
+        let (t_fine, temp) = compensate_temperature(raw_temp, &cal);
+        Measurement {
+            celsius: temp as f32 / 100.0,
+            pressure: compensate_pressure(raw_pressure, t_fine, &cal) as f32 / 256.0,
+            humidity: raw_humidity
+                .map(|raw| compensate_humidity(raw, t_fine, &cal) as f32 / 1024.0),
+        }
+    }
+}
+
+// Compensation from the datasheet's integer reference code.
+
+/// Return t_fine, used by the other compensations, and hundredths of a degree celsius.
+pub fn compensate_temperature(raw_temp: i32, cal: &Calibration) -> (i32, i32) {
+    let t1 = cal.dig_t1 as i32;
+    let var1 = (((raw_temp >> 3) - (t1 << 1)) * cal.dig_t2 as i32) >> 11;
+    let var2 =
+        (((((raw_temp >> 4) - t1) * ((raw_temp >> 4) - t1)) >> 12) * cal.dig_t3 as i32) >> 14;
+    let t_fine = var1 + var2;
+    (t_fine, (t_fine * 5 + 128) >> 8)
+}
+
+/// Pascal in Q24.8 fixed point; divide by 256.
+pub fn compensate_pressure(raw_pressure: i32, t_fine: i32, cal: &Calibration) -> u32 {
+    let mut var1 = t_fine as i64 - 128_000;
+    let mut var2 = var1 * var1 * cal.dig_p6 as i64;
+    var2 += (var1 * cal.dig_p5 as i64) << 17;
+    var2 += (cal.dig_p4 as i64) << 35;
+    var1 = ((var1 * var1 * cal.dig_p3 as i64) >> 8) + ((var1 * cal.dig_p2 as i64) << 12);
+    var1 = (((1_i64 << 47) + var1) * cal.dig_p1 as i64) >> 33;
+    if var1 == 0 {
+        // Avoid dividing by zero on blank calibration.
+        return 0;
+    }
+    let mut p = 1_048_576 - raw_pressure as i64;
+    p = (((p << 31) - var2) * 3125) / var1;
+    var1 = (cal.dig_p9 as i64 * (p >> 13) * (p >> 13)) >> 25;
+    var2 = (cal.dig_p8 as i64 * p) >> 19;
+    (((p + var1 + var2) >> 8) + ((cal.dig_p7 as i64) << 4)) as u32
+}
+
+/// Percent relative humidity in Q22.10 fixed point, 0 to 100 %; divide by 1024.
+///
+/// Worked in i64 so that a bad calibration gives a wrong answer rather than an overflow; with
+/// real trimming values it matches the datasheet's 32 bit code.
+pub fn compensate_humidity(raw_humidity: i32, t_fine: i32, cal: &Calibration) -> u32 {
+    let mut v = t_fine as i64 - 76_800;
+    v = (((((raw_humidity as i64) << 14) - ((cal.dig_h4 as i64) << 20) - (cal.dig_h5 as i64 * v))
+        + 16_384)
+        >> 15)
+        * (((((((v * cal.dig_h6 as i64) >> 10) * (((v * cal.dig_h3 as i64) >> 11) + 32_768))
+            >> 10)
+            + 2_097_152)
+            * cal.dig_h2 as i64
+            + 8192)
+            >> 14);
+    v -= ((((v >> 15) * (v >> 15)) >> 7) * cal.dig_h1 as i64) >> 4;
+    (v.clamp(0, 419_430_400) >> 12) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Worked example from the BMP280 datasheet, section 8.2.
+    fn datasheet_calibration() -> Calibration {
+        Calibration {
+            dig_t1: 27504,
+            dig_t2: 26435,
+            dig_t3: -1000,
+            dig_p1: 36477,
+            dig_p2: -10685,
+            dig_p3: 3024,
+            dig_p4: 2855,
+            dig_p5: 140,
+            dig_p6: -7,
+            dig_p7: 15500,
+            dig_p8: -14600,
+            dig_p9: 6000,
+            ..Calibration::default()
+        }
+    }
+
+    #[test]
+    fn test_compensate_datasheet_example() {
+        let cal = datasheet_calibration();
+        let (t_fine, temp) = compensate_temperature(519_888, &cal);
+        assert_eq!(t_fine, 128_422);
+        assert_eq!(temp, 2508);
+        let pressure = compensate_pressure(415_148, t_fine, &cal);
+        assert_eq!(pressure / 256, 100_653);
+    }
+
+    #[test]
+    fn test_blank_calibration_does_not_divide_by_zero() {
+        assert_eq!(compensate_pressure(415_148, 0, &Calibration::default()), 0);
+    }
+
+    // Typical BME280 humidity trimming on top of the datasheet's temperature and pressure.
+    fn humidity_calibration() -> Calibration {
+        Calibration {
+            dig_h1: 75,
+            dig_h2: 362,
+            dig_h3: 0,
+            dig_h4: 313,
+            dig_h5: 50,
+            dig_h6: 30,
+            ..datasheet_calibration()
+        }
+    }
+
+    #[test]
+    fn test_compensate_humidity() {
+        let cal = humidity_calibration();
+        // The datasheet's floating point formula gives 55.0007 % for these.
+        let humidity = compensate_humidity(30_000, 128_422, &cal);
+        assert_eq!(humidity, 56_317);
+        assert!((humidity as f32 / 1024.0 - 55.0007).abs() < 0.01);
+        // Clamped to 0 to 100 %.
+        assert_eq!(compensate_humidity(20_000, 128_422, &cal), 0);
+        assert_eq!(compensate_humidity(50_000, 128_422, &cal), 100 * 1024);
+        assert_eq!(compensate_humidity(0xFFFF, 128_422, &cal), 100 * 1024);
+    }
+
+    #[test]
+    fn test_blank_calibration_rejected() {
+        let cal = humidity_calibration();
+        assert_eq!(cal.check(Chip::Bme280), Ok(()));
+        let zeros = Calibration::default();
+        assert_eq!(
+            zeros.check(Chip::Bmp280),
+            Err(BarometerError::BadCalibration {
+                register: CALIB_TP,
+                value: 0
+            })
+        );
+        let no_humidity = Calibration { dig_h2: -1, ..cal };
+        assert!(no_humidity.check(Chip::Bmp280).is_ok());
+        assert_eq!(
+            no_humidity.check(Chip::Bme280),
+            Err(BarometerError::BadCalibration {
+                register: CALIB_H2,
+                value: 0xFFFF
+            })
+        );
+        // Whatever the calibration, compensation doesn't overflow.
+        let ones = Calibration {
+            dig_h1: 0xFF,
+            dig_h2: -1,
+            dig_h3: 0xFF,
+            dig_h4: -1,
+            dig_h5: -1,
+            dig_h6: -1,
+            ..cal
+        };
+        for raw in [0, 0xFFFF] {
+            assert!(compensate_humidity(raw, i32::MAX / 2, &ones) <= 100 * 1024);
+        }
+    }
+}
@@ -0,0 +1,167 @@
+// Station settings kept between runs, as JSON next to the binary.
+//
+// Every mode loads them; command line flags override them for that run.
+
+use crate::altimetry::STANDARD_PRESSURE;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+
+pub const CONFIG_PATH: &str = "pi_play.json";
+
+const PA_PER_INHG: f32 = 3386.389;
+const PA_PER_MMHG: f32 = 133.322;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+}
+
+impl TemperatureUnit {
+    pub fn format(&self, celsius: f32) -> String {
+        match self {
+            TemperatureUnit::Celsius => format!("{:.1} C", celsius),
+            TemperatureUnit::Fahrenheit => format!("{:.1} F", celsius * 9.0 / 5.0 + 32.0),
+        }
+    }
+
+    pub fn next(&self) -> TemperatureUnit {
+        match self {
+            TemperatureUnit::Celsius => TemperatureUnit::Fahrenheit,
+            TemperatureUnit::Fahrenheit => TemperatureUnit::Celsius,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            TemperatureUnit::Celsius => "C",
+            TemperatureUnit::Fahrenheit => "F",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PressureUnit {
+    Hpa,
+    InHg,
+    MmHg,
+}
+
+impl PressureUnit {
+    /// From Pa.
+    pub fn format(&self, pressure: f32) -> String {
+        match self {
+            PressureUnit::Hpa => format!("{:.1} hPa", pressure / 100.0),
+            PressureUnit::InHg => format!("{:.2} inHg", pressure / PA_PER_INHG),
+            PressureUnit::MmHg => format!("{:.1} mmHg", pressure / PA_PER_MMHG),
+        }
+    }
+
+    pub fn next(&self) -> PressureUnit {
+        match self {
+            PressureUnit::Hpa => PressureUnit::InHg,
+            PressureUnit::InHg => PressureUnit::MmHg,
+            PressureUnit::MmHg => PressureUnit::Hpa,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            PressureUnit::Hpa => "hPa",
+            PressureUnit::InHg => "inHg",
+            PressureUnit::MmHg => "mmHg",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    pub temperature_unit: TemperatureUnit,
+    pub pressure_unit: PressureUnit,
+    /// Sea level pressure for altitude, Pa.
+    pub qnh: f32,
+    /// Station elevation in metres, when known.
+    pub elevation: Option<f32>,
+    pub backlight: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            temperature_unit: TemperatureUnit::Celsius,
+            pressure_unit: PressureUnit::Hpa,
+            qnh: STANDARD_PRESSURE,
+            elevation: None,
+            backlight: true,
+        }
+    }
+}
+
+impl Config {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Config should serialize")
+    }
+
+    pub fn from_json(json: &str) -> Result<Config, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Defaults when the file is missing or unreadable.
+    pub fn load(path: &str) -> Config {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|json| Config::from_json(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+
+    /// Apply `--qnh=<hPa>`, `--celsius` and `--fahrenheit` on top of the saved settings.
+    pub fn with_args(mut self, args: impl Iterator<Item = String>) -> Config {
+        for arg in args {
+            if let Some(hpa) = arg.strip_prefix("--qnh=") {
+                self.qnh = hpa.parse::<f32>().expect("QNH should be in hPa") * 100.0;
+            }
+            match arg.as_str() {
+                "--celsius" => self.temperature_unit = TemperatureUnit::Celsius,
+                "--fahrenheit" => self.temperature_unit = TemperatureUnit::Fahrenheit,
+                _ => {}
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_json_round_trip() {
+        let config = Config {
+            pressure_unit: PressureUnit::InHg,
+            elevation: Some(540.0),
+            ..Config::default()
+        };
+        assert_eq!(Config::from_json(&config.to_json()).unwrap(), config);
+    }
+
+    #[test]
+    fn test_args_override() {
+        let args = ["pi_play", "--vario", "--qnh=1020.5", "--fahrenheit"];
+        let config = Config::default().with_args(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(config.qnh, 102_050.0);
+        assert_eq!(config.temperature_unit, TemperatureUnit::Fahrenheit);
+        assert_eq!(config.pressure_unit, PressureUnit::Hpa);
+    }
+
+    #[test]
+    fn test_units() {
+        assert_eq!(TemperatureUnit::Fahrenheit.format(100.0), "212.0 F");
+        assert_eq!(PressureUnit::InHg.format(STANDARD_PRESSURE), "29.92 inHg");
+        assert_eq!(PressureUnit::MmHg.format(STANDARD_PRESSURE), "760.0 mmHg");
+    }
+}
@@ -1,46 +1,275 @@
+// HC-SR04 ultrasonic rangefinder. A 10 us pulse on TRIG sends a burst; ECHO then stays high
+// for the round trip time.
+// Datasheet: https://cdn.sparkfun.com/datasheets/Sensors/Proximity/HCSR04.pdf
+//
+// let mut distance = Distance::new();
+// distance.set_temperature(read_temp(false))?; // or a barometer's celsius
+// distance.measure()?.centimetres();
+
 use gpio::GpioValue::{High, Low};
 use gpio::{GpioIn, GpioOut};
+use std::fmt;
+use std::io;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+const TRIG_PIN: u16 = 23;
+const ECHO_PIN: u16 = 24;
+
+// ECHO rises about half a millisecond after the trigger; allow 10 ms for scheduling delays.
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(10);
+
+// With no echo the sensor holds ECHO high for about 38 ms, and the datasheet asks for 60 ms
+// from one trigger to the next.
+const ECHO_IDLE_TIMEOUT: Duration = Duration::from_millis(50);
+const MEASUREMENT_CYCLE: Duration = Duration::from_millis(60);
+
+// Air temperatures accepted for the speed of sound, well beyond what the sensor works in.
+const MIN_CELSIUS: f32 = -50.0;
+const MAX_CELSIUS: f32 = 100.0;
+
+/// A distance, stored in metres.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Length(f32);
+
+impl Length {
+    pub fn from_metres(metres: f32) -> Length {
+        Length(metres)
+    }
+
+    pub fn from_centimetres(centimetres: f32) -> Length {
+        Length(centimetres / 100.0)
+    }
+
+    pub fn metres(&self) -> f32 {
+        self.0
+    }
+
+    pub fn centimetres(&self) -> f32 {
+        self.0 * 100.0
+    }
+
+    pub fn millimetres(&self) -> f32 {
+        self.0 * 1000.0
+    }
+
+    pub fn inches(&self) -> f32 {
+        self.0 / 0.0254
+    }
+}
+
+impl fmt::Display for Length {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1} cm", self.centimetres())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DistanceError {
+    /// ECHO never went high; check the wiring and the 5 V supply.
+    NoResponse,
+    /// Nothing within the maximum range reflected the burst.
+    NoEcho,
+    /// ECHO was still high from an earlier burst.
+    Busy,
+    /// Reading ECHO or driving TRIG failed.
+    Gpio(io::ErrorKind),
+    /// A maximum range that is not a positive length, in metres.
+    BadRange(f32),
+    /// An air temperature that is not a number or out of range, in celsius.
+    BadTemperature(f32),
+}
+
+impl fmt::Display for DistanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DistanceError::NoResponse => write!(f, "sensor did not respond to the trigger"),
+            DistanceError::NoEcho => write!(f, "no echo within range"),
+            DistanceError::Busy => write!(f, "echo line still high from the last burst"),
+            DistanceError::Gpio(kind) => write!(f, "GPIO access failed; {kind}"),
+            DistanceError::BadRange(metres) => write!(f, "range {metres} m is not positive"),
+            DistanceError::BadTemperature(celsius) => {
+                write!(f, "air temperature {celsius} C is out of range")
+            }
+        }
+    }
+}
+
+impl From<io::Error> for DistanceError {
+    fn from(e: io::Error) -> DistanceError {
+        DistanceError::Gpio(e.kind())
+    }
+}
+
+impl std::error::Error for DistanceError {}
+
+/// Metres per second in dry air.
+pub fn speed_of_sound(celsius: f32) -> f32 {
+    331.3 * f32::sqrt(1.0 + celsius / 273.15)
+}
+
+/// Distance to whatever returned an echo after `echo` of round trip.
+pub fn length_from_echo(echo: Duration, celsius: f32) -> Length {
+    Length(echo.as_secs_f32() * speed_of_sound(celsius) / 2.0)
+}
+
+/// Round trip time to `range` and back.
+pub fn echo_time(range: Length, celsius: f32) -> Duration {
+    Duration::from_secs_f32(2.0 * range.metres() / speed_of_sound(celsius))
+}
+
+/// A range the echo timeout can be worked out for: finite and above zero.
+fn check_range(range: Length) -> Result<Length, DistanceError> {
+    match range.metres().is_finite() && range.metres() > 0.0 {
+        true => Ok(range),
+        false => Err(DistanceError::BadRange(range.metres())),
+    }
+}
+
+/// An air temperature the speed of sound can be worked out for.
+fn check_temperature(celsius: f32) -> Result<f32, DistanceError> {
+    match (MIN_CELSIUS..=MAX_CELSIUS).contains(&celsius) {
+        true => Ok(celsius),
+        false => Err(DistanceError::BadTemperature(celsius)),
+    }
+}
+
+/// Five characters for the segment display: centimetres, or dashes without a reading.
+pub fn display_string(reading: Result<Length, DistanceError>) -> String {
+    match reading {
+        Ok(length) => format!("{:05.1}", length.centimetres().clamp(0.0, 999.9)),
+        Err(_) => "---.-".to_string(),
+    }
+}
 
 pub struct Distance {
     in_: gpio::sysfs::SysFsGpioInput,
     out: gpio::sysfs::SysFsGpioOutput,
+    celsius: f32,
+    max_range: Length,
+    last_trigger: Option<Instant>,
 }
 
 impl Distance {
     pub fn new() -> Distance {
-        let in_ = gpio::sysfs::SysFsGpioInput::open(24).unwrap();
-        let mut out = gpio::sysfs::SysFsGpioOutput::open(23).unwrap();
+        let in_ = gpio::sysfs::SysFsGpioInput::open(ECHO_PIN).unwrap();
+        let mut out = gpio::sysfs::SysFsGpioOutput::open(TRIG_PIN).unwrap();
         out.set_value(false).unwrap();
         thread::sleep(Duration::from_secs(2));
-        Self { in_, out }
+        Self {
+            in_,
+            out,
+            celsius: 20.0,
+            max_range: Length(4.0),
+            last_trigger: None,
+        }
     }
 
-    fn measure(&mut self) -> f64 {
-        self.out.set_value(true).unwrap();
-        thread::sleep(Duration::from_micros(15));
-        self.out.set_value(false).unwrap();
-        while self.in_.read_value().unwrap() == Low {
-            continue;
+    /// Air temperature for the speed of sound, about 0.17 % per degree. Defaults to 20 C.
+    pub fn set_temperature(&mut self, celsius: f32) -> Result<(), DistanceError> {
+        self.celsius = check_temperature(celsius)?;
+        Ok(())
+    }
+
+    /// Echoes from further than this are ignored. Defaults to the rated 4 m.
+    pub fn set_max_range(&mut self, max_range: Length) -> Result<(), DistanceError> {
+        self.max_range = check_range(max_range)?;
+        Ok(())
+    }
+
+    /// Spin until ECHO reads `level`, returning when it did, or `timed_out` after `timeout`.
+    fn wait_for(
+        &mut self,
+        level: gpio::GpioValue,
+        timeout: Duration,
+        timed_out: DistanceError,
+    ) -> Result<Instant, DistanceError> {
+        let start = Instant::now();
+        loop {
+            if self.in_.read_value()? == level {
+                return Ok(Instant::now());
+            }
+            if start.elapsed() > timeout {
+                return Err(timed_out);
+            }
         }
-        let t1 = chrono::Utc::now();
-        while self.in_.read_value().unwrap() == High {
-            continue;
+    }
+
+    /// Blocks for up to the 60 ms measurement cycle since the last call, then for the echo.
+    pub fn measure(&mut self) -> Result<Length, DistanceError> {
+        if let Some(last) = self.last_trigger {
+            let elapsed = last.elapsed();
+            if elapsed < MEASUREMENT_CYCLE {
+                thread::sleep(MEASUREMENT_CYCLE - elapsed);
+            }
+        }
+        // A missed echo leaves ECHO high for a while; triggering now would time that.
+        self.wait_for(Low, ECHO_IDLE_TIMEOUT, DistanceError::Busy)?;
+        self.last_trigger = Some(Instant::now());
+        self.out.set_value(true)?;
+        thread::sleep(Duration::from_micros(15));
+        self.out.set_value(false)?;
+        let rise = self.wait_for(High, RESPONSE_TIMEOUT, DistanceError::NoResponse)?;
+        let fall = self.wait_for(
+            Low,
+            echo_time(self.max_range, self.celsius),
+            DistanceError::NoEcho,
+        )?;
+        let length = length_from_echo(fall - rise, self.celsius);
+        match length > self.max_range {
+            true => Err(DistanceError::NoEcho),
+            false => Ok(length),
         }
-        let t2 = chrono::Utc::now();
-        let distance = (t2 - t1).num_microseconds().unwrap() as f64 * 340.0 / 2.0;
-        distance
     }
 
     pub fn print_measure(&mut self) -> String {
-        let reading = self.measure();
-        // println!("reading {}", reading);
-        let mut print_string = reading.to_string();
-        let num_pad = 8 - print_string.len();
-        for _ in 0..num_pad {
-            print_string = "0".to_string() + &print_string;
+        display_string(self.measure())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speed_of_sound() {
+        assert!((speed_of_sound(20.0) - 343.2).abs() < 0.1);
+        assert!((speed_of_sound(0.0) - 331.3).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_echo_round_trip() {
+        // About 5.8 ms there and back for a metre at 20 C.
+        let length = length_from_echo(Duration::from_micros(5828), 20.0);
+        assert!((length.centimetres() - 100.0).abs() < 0.1);
+        let echo = echo_time(Length::from_metres(1.0), 20.0);
+        assert!((echo.as_secs_f32() - 0.005_828).abs() < 0.000_01);
+        // Cold air is slower, so the same echo is a shorter distance.
+        assert!(length_from_echo(Duration::from_micros(5828), -10.0) < length);
+    }
+
+    #[test]
+    fn test_bad_settings_rejected() {
+        assert!(check_range(Length::from_metres(2.0)).is_ok());
+        for metres in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(matches!(
+                check_range(Length::from_metres(metres)),
+                Err(DistanceError::BadRange(_))
+            ));
+        }
+        assert_eq!(check_temperature(-10.0), Ok(-10.0));
+        for celsius in [-300.0, 150.0, f32::NAN, f32::NEG_INFINITY] {
+            assert!(matches!(
+                check_temperature(celsius),
+                Err(DistanceError::BadTemperature(_))
+            ));
         }
-        print_string[1..6].to_string()
+    }
+
+    #[test]
+    fn test_display_string() {
+        assert_eq!(display_string(Ok(Length::from_centimetres(12.34))), "012.3");
+        assert_eq!(display_string(Ok(Length::from_metres(25.0))), "999.9");
+        assert_eq!(display_string(Err(DistanceError::NoEcho)), "---.-");
     }
 }
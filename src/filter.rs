@@ -0,0 +1,49 @@
+// Smoothing shared by the sensor and control modules.
+
+/// Single pole IIR (exponential moving average) filter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Iir {
+    alpha: f32,
+    value: Option<f32>,
+}
+
+impl Iir {
+    pub fn new(alpha: f32) -> Iir {
+        Iir {
+            alpha: alpha.clamp(0.0, 1.0),
+            value: None,
+        }
+    }
+
+    /// Feed a sample, return the filtered value. The first sample passes straight through.
+    pub fn update(&mut self, sample: f32) -> f32 {
+        let value = match self.value {
+            Some(value) => value + self.alpha * (sample - value),
+            None => sample,
+        };
+        self.value = Some(value);
+        value
+    }
+
+    pub fn value(&self) -> Option<f32> {
+        self.value
+    }
+
+    /// Forget the state; the next sample passes straight through again.
+    pub fn reset(&mut self) {
+        self.value = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iir_converges() {
+        let mut iir = Iir::new(0.5);
+        assert_eq!(iir.update(10.0), 10.0);
+        assert_eq!(iir.update(20.0), 15.0);
+        assert_eq!(iir.update(20.0), 17.5);
+    }
+}
@@ -1,32 +1,611 @@
+// Two axis joystick with push button, axes on ADC channels 0 and 1.
+//
+// let mut joy_stick = JoyStick::new();
+// joy_stick.calibrate(Duration::from_secs(5)); // leave centred, then circle the stick
+// joy_stick.calibration().save(CALIBRATION_PATH).unwrap();
+// joy_stick.output().direction;
+//
+// let events = Events::spawn(joy_stick, Settings::default());
+// while let Ok(event) = events.receiver().recv() { ... }
+
 use crate::adc_0832::ADC;
+use crate::analog::{AdcError, AnalogInput, Input};
+use crate::worker::Worker;
 use gpio::GpioIn;
-use gpio::GpioValue::{High, Low};
+use gpio::GpioValue::High;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const CALIBRATION_PATH: &str = "joystick.json";
 
 const BUTTON_PIN: u16 = 24;
 
-pub struct JoyStick {
-    acd: ADC,
+/// Raw counts at each end and at rest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AxisCalibration {
+    pub min: u8,
+    pub center: u8,
+    pub max: u8,
+    /// Flip the sign, for an axis mounted the other way round.
+    pub invert: bool,
+}
+
+impl Default for AxisCalibration {
+    fn default() -> AxisCalibration {
+        AxisCalibration {
+            min: 0,
+            center: 128,
+            max: 255,
+            invert: false,
+        }
+    }
+}
+
+impl AxisCalibration {
+    /// -1.0 to 1.0, 0.0 at center. Readings within `dead_zone` of center are 0.0 and the
+    /// rest of the travel is rescaled so the output still reaches 1.0.
+    pub fn normalize(&self, raw: u8, dead_zone: f32) -> f32 {
+        let offset = raw as f32 - self.center as f32;
+        let span = match offset < 0.0 {
+            true => self.center as f32 - self.min as f32,
+            false => self.max as f32 - self.center as f32,
+        };
+        if span <= 0.0 {
+            return 0.0;
+        }
+        let value = (offset / span).clamp(-1.0, 1.0);
+        let value = match self.invert {
+            true => -value,
+            false => value,
+        };
+        if value.abs() <= dead_zone {
+            0.0
+        } else {
+            value.signum() * (value.abs() - dead_zone) / (1.0 - dead_zone)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub horizontal: AxisCalibration,
+    pub vertical: AxisCalibration,
+}
+
+impl Calibration {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Calibration should serialize")
+    }
+
+    pub fn from_json(json: &str) -> Result<Calibration, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+
+    pub fn load(path: &str) -> io::Result<Calibration> {
+        Ok(Calibration::from_json(&fs::read_to_string(path)?)?)
+    }
+}
+
+/// Eight way direction, up being positive vertical.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Center,
+    Up,
+    UpRight,
+    Right,
+    DownRight,
+    Down,
+    DownLeft,
+    Left,
+    UpLeft,
+}
+
+impl Direction {
+    /// Nearest of the eight directions to normalized axes; Center when both are zero.
+    pub fn from_axes(x: f32, y: f32) -> Direction {
+        if x == 0.0 && y == 0.0 {
+            return Direction::Center;
+        }
+        // Sectors 45 degrees wide counting anticlockwise from Right.
+        let sector = (f32::atan2(y, x) / std::f32::consts::FRAC_PI_4).round() as i32;
+        match sector.rem_euclid(8) {
+            0 => Direction::Right,
+            1 => Direction::UpRight,
+            2 => Direction::Up,
+            3 => Direction::UpLeft,
+            4 => Direction::Left,
+            5 => Direction::DownLeft,
+            6 => Direction::Down,
+            _ => Direction::DownRight,
+        }
+    }
+}
+
+/// Normalized reading.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct State {
+    /// -1.0 left to 1.0 right.
+    pub x: f32,
+    /// -1.0 down to 1.0 up.
+    pub y: f32,
+    pub direction: Direction,
+    pub pressed: bool,
+}
+
+pub struct JoyStick<A: AnalogInput = ADC> {
+    acd: A,
     button: gpio::sysfs::SysFsGpioInput,
+    calibration: Calibration,
+    dead_zone: f32,
 }
 
 impl JoyStick {
     pub fn new() -> JoyStick {
-        let acd = ADC::new();
+        JoyStick::with_adc(ADC::new())
+    }
+}
+
+impl<A: AnalogInput> JoyStick<A> {
+    /// Axes on channels 0 and 1 of any ADC, e.g. a `Pcf8591`.
+    pub fn with_adc(acd: A) -> JoyStick<A> {
         let button = gpio::sysfs::SysFsGpioInput::open(BUTTON_PIN).expect("Pin is active");
 
-        Self { acd, button }
+        Self {
+            acd,
+            button,
+            calibration: Calibration::default(),
+            dead_zone: 0.1,
+        }
+    }
+
+    pub fn calibration(&self) -> Calibration {
+        self.calibration
     }
 
-    pub fn output(&mut self) -> (u8, u8, u8) {
-        let horizontal = self.acd.get_result(0);
-        let vertical = self.acd.get_result(1);
-        let pressed = match self.button.read_value().expect("Pin is read") {
-            High => {
-                println!("pressed");
-                1
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+
+    /// Fraction of travel around center that reads as 0.0. Defaults to 0.1.
+    pub fn set_dead_zone(&mut self, dead_zone: f32) {
+        self.dead_zone = dead_zone.clamp(0.0, 0.99);
+    }
+
+    /// Horizontal count, vertical count and whether the button is down.
+    ///
+    /// A conversion that fails its check is an error; callers decide whether to retry.
+    pub fn raw(&mut self) -> Result<(u8, u8, bool), AdcError> {
+        let horizontal = self.acd.read(Input::SingleEnded(0))?;
+        let vertical = self.acd.read(Input::SingleEnded(1))?;
+        let pressed = self.button.read_value().expect("Pin is read") == High;
+        Ok((horizontal, vertical, pressed))
+    }
+
+    pub fn output(&mut self) -> Result<State, AdcError> {
+        let (horizontal, vertical, pressed) = self.raw()?;
+        let x = self
+            .calibration
+            .horizontal
+            .normalize(horizontal, self.dead_zone);
+        let y = self
+            .calibration
+            .vertical
+            .normalize(vertical, self.dead_zone);
+        Ok(State {
+            x,
+            y,
+            direction: Direction::from_axes(x, y),
+            pressed,
+        })
+    }
+
+    /// Take the resting position as center, then record the extremes reached while the
+    /// stick is moved around its full travel for `duration`. Keeps axis inversion.
+    ///
+    /// Failed reads while moving are skipped; only a failed center read is an error.
+    pub fn calibrate(&mut self, duration: Duration) -> Result<Calibration, AdcError> {
+        let (center_x, center_y, _) = self.raw()?;
+        let mut horizontal = AxisCalibration {
+            min: center_x,
+            center: center_x,
+            max: center_x,
+            invert: self.calibration.horizontal.invert,
+        };
+        let mut vertical = AxisCalibration {
+            min: center_y,
+            center: center_y,
+            max: center_y,
+            invert: self.calibration.vertical.invert,
+        };
+        let start = Instant::now();
+        while start.elapsed() < duration {
+            if let Ok((x, y, _)) = self.raw() {
+                horizontal.min = horizontal.min.min(x);
+                horizontal.max = horizontal.max.max(x);
+                vertical.min = vertical.min.min(y);
+                vertical.max = vertical.max.max(y);
             }
-            Low => 0,
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.calibration = Calibration {
+            horizontal,
+            vertical,
+        };
+        Ok(self.calibration)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventKind {
+    Pressed,
+    Released,
+    /// Held for `Settings::long_press`; sent while still held, the release follows.
+    LongPress,
+    /// Two short presses within `Settings::double_click`, sent on the second release.
+    DoubleClick,
+    /// Moved into a direction other than Center.
+    Entered(Direction),
+    Left(Direction),
+    /// Moved past `Settings::threshold` either way.
+    Beyond {
+        axis: Axis,
+        value: f32,
+    },
+    /// Came back within `Settings::threshold`.
+    Within {
+        axis: Axis,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Event {
+    pub at: Instant,
+    pub kind: EventKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    /// How long the button must hold a new state before it counts.
+    pub debounce: Duration,
+    pub long_press: Duration,
+    /// Longest gap between two short presses' releases for a double click.
+    pub double_click: Duration,
+    /// Normalized axis value for `Beyond`.
+    pub threshold: f32,
+    pub poll: Duration,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            debounce: Duration::from_millis(30),
+            long_press: Duration::from_millis(800),
+            double_click: Duration::from_millis(400),
+            threshold: 0.5,
+            poll: Duration::from_millis(10),
+        }
+    }
+}
+
+/// Turns successive `State`s into events.
+pub struct Gestures {
+    settings: Settings,
+    // Undebounced button and when it last changed.
+    raw_pressed: bool,
+    raw_since: Option<Instant>,
+    pressed: bool,
+    pressed_at: Option<Instant>,
+    long_sent: bool,
+    last_click: Option<Instant>,
+    direction: Direction,
+    beyond: [bool; 2],
+}
+
+impl Gestures {
+    pub fn new(settings: Settings) -> Gestures {
+        Gestures {
+            settings,
+            raw_pressed: false,
+            raw_since: None,
+            pressed: false,
+            pressed_at: None,
+            long_sent: false,
+            last_click: None,
+            direction: Direction::Center,
+            beyond: [false; 2],
+        }
+    }
+
+    /// Events caused by `state` read at `at`, in the order they happened.
+    pub fn update(&mut self, state: State, at: Instant) -> Vec<Event> {
+        let mut kinds = Vec::new();
+        self.update_button(state.pressed, at, &mut kinds);
+
+        if state.direction != self.direction {
+            if self.direction != Direction::Center {
+                kinds.push(EventKind::Left(self.direction));
+            }
+            if state.direction != Direction::Center {
+                kinds.push(EventKind::Entered(state.direction));
+            }
+            self.direction = state.direction;
+        }
+
+        for (i, (axis, value)) in [(Axis::Horizontal, state.x), (Axis::Vertical, state.y)]
+            .into_iter()
+            .enumerate()
+        {
+            let beyond = value.abs() > self.settings.threshold;
+            if beyond != self.beyond[i] {
+                self.beyond[i] = beyond;
+                kinds.push(match beyond {
+                    true => EventKind::Beyond { axis, value },
+                    false => EventKind::Within { axis },
+                });
+            }
+        }
+
+        kinds.into_iter().map(|kind| Event { at, kind }).collect()
+    }
+
+    fn update_button(&mut self, pressed: bool, at: Instant, kinds: &mut Vec<EventKind>) {
+        if pressed != self.raw_pressed || self.raw_since.is_none() {
+            self.raw_pressed = pressed;
+            self.raw_since = Some(at);
+        }
+        let stable = self
+            .raw_since
+            .is_some_and(|since| at - since >= self.settings.debounce);
+
+        if stable && self.raw_pressed != self.pressed {
+            self.pressed = self.raw_pressed;
+            if self.pressed {
+                kinds.push(EventKind::Pressed);
+                self.pressed_at = Some(at);
+                self.long_sent = false;
+            } else {
+                kinds.push(EventKind::Released);
+                if !self.long_sent {
+                    match self.last_click {
+                        Some(last) if at - last <= self.settings.double_click => {
+                            kinds.push(EventKind::DoubleClick);
+                            self.last_click = None;
+                        }
+                        _ => self.last_click = Some(at),
+                    }
+                }
+            }
+        }
+
+        if let (true, false, Some(pressed_at)) = (self.pressed, self.long_sent, self.pressed_at) {
+            if at - pressed_at >= self.settings.long_press {
+                kinds.push(EventKind::LongPress);
+                self.long_sent = true;
+                self.last_click = None;
+            }
+        }
+    }
+}
+
+/// Reads the joystick on a background thread and sends events over a channel.
+///
+/// A failed read skips that poll and is counted.
+pub struct Events {
+    receiver: mpsc::Receiver<Event>,
+    errors: Arc<AtomicU32>,
+    worker: Worker,
+}
+
+impl Events {
+    pub fn spawn<A: AnalogInput + Send + 'static>(
+        mut joy_stick: JoyStick<A>,
+        settings: Settings,
+    ) -> Events {
+        let (sender, receiver) = mpsc::channel();
+        let errors = Arc::new(AtomicU32::new(0));
+        let thread_errors = Arc::clone(&errors);
+        let worker = Worker::spawn("joystick", move |running| {
+            let mut gestures = Gestures::new(settings);
+            while running.load(Ordering::Relaxed) {
+                match joy_stick.output() {
+                    Ok(state) => {
+                        for event in gestures.update(state, Instant::now()) {
+                            if sender.send(event).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(_) => {
+                        thread_errors.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                thread::sleep(settings.poll);
+            }
+        });
+        Events {
+            receiver,
+            errors,
+            worker,
+        }
+    }
+
+    pub fn receiver(&self) -> &mpsc::Receiver<Event> {
+        &self.receiver
+    }
+
+    /// Polls skipped for failed reads.
+    pub fn errors(&self) -> u32 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    pub fn stop(&mut self) {
+        self.worker.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_asymmetric_axis() {
+        let axis = AxisCalibration {
+            min: 20,
+            center: 120,
+            max: 220,
+            invert: false,
+        };
+        assert_eq!(axis.normalize(120, 0.0), 0.0);
+        assert_eq!(axis.normalize(20, 0.0), -1.0);
+        assert_eq!(axis.normalize(0, 0.0), -1.0);
+        assert_eq!(axis.normalize(170, 0.0), 0.5);
+        // The dead zone swallows small offsets and the rest is stretched to full scale.
+        assert_eq!(axis.normalize(125, 0.1), 0.0);
+        assert!((axis.normalize(170, 0.2) - 0.375).abs() < 1e-6);
+        assert_eq!(axis.normalize(220, 0.2), 1.0);
+    }
+
+    #[test]
+    fn test_direction() {
+        assert_eq!(Direction::from_axes(0.0, 0.0), Direction::Center);
+        assert_eq!(Direction::from_axes(0.0, 1.0), Direction::Up);
+        assert_eq!(Direction::from_axes(0.7, -0.7), Direction::DownRight);
+        assert_eq!(Direction::from_axes(-1.0, 0.2), Direction::Left);
+    }
+
+    fn button(pressed: bool) -> State {
+        State {
+            x: 0.0,
+            y: 0.0,
+            direction: Direction::Center,
+            pressed,
+        }
+    }
+
+    // Feed one state per 10 ms and collect the event kinds.
+    fn replay(gestures: &mut Gestures, start: Instant, states: &[(u64, State)]) -> Vec<EventKind> {
+        states
+            .iter()
+            .flat_map(|&(ms, state)| gestures.update(state, start + Duration::from_millis(ms)))
+            .map(|event| event.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_button_debounce_and_double_click() {
+        let mut gestures = Gestures::new(Settings::default());
+        let start = Instant::now();
+        let mut states = Vec::new();
+        // Bounce, a click, then a second click 200 ms later.
+        for (ms, pressed) in [(0, false), (10, true), (20, false), (30, true)] {
+            states.push((ms, button(pressed)));
+        }
+        for ms in (40..=100).step_by(10) {
+            states.push((ms, button(true)));
+        }
+        for ms in (110..=300).step_by(10) {
+            states.push((ms, button(false)));
+        }
+        for ms in (310..=400).step_by(10) {
+            states.push((ms, button(true)));
+        }
+        for ms in (410..=500).step_by(10) {
+            states.push((ms, button(false)));
+        }
+        assert_eq!(
+            replay(&mut gestures, start, &states),
+            vec![
+                EventKind::Pressed,
+                EventKind::Released,
+                EventKind::Pressed,
+                EventKind::Released,
+                EventKind::DoubleClick,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_long_press() {
+        let mut gestures = Gestures::new(Settings::default());
+        let start = Instant::now();
+        let mut states: Vec<(u64, State)> = (0..=1000)
+            .step_by(10)
+            .map(|ms| (ms, button(true)))
+            .collect();
+        states.push((1100, button(false)));
+        states.push((1200, button(false)));
+        assert_eq!(
+            replay(&mut gestures, start, &states),
+            vec![
+                EventKind::Pressed,
+                EventKind::LongPress,
+                EventKind::Released
+            ]
+        );
+    }
+
+    #[test]
+    fn test_direction_and_axis_events() {
+        let mut gestures = Gestures::new(Settings::default());
+        let start = Instant::now();
+        let up = State {
+            x: 0.0,
+            y: 0.9,
+            direction: Direction::Up,
+            pressed: false,
+        };
+        let right = State {
+            x: 0.3,
+            y: 0.0,
+            direction: Direction::Right,
+            pressed: false,
+        };
+        assert_eq!(
+            replay(&mut gestures, start, &[(0, up), (10, right)]),
+            vec![
+                EventKind::Entered(Direction::Up),
+                EventKind::Beyond {
+                    axis: Axis::Vertical,
+                    value: 0.9
+                },
+                EventKind::Left(Direction::Up),
+                EventKind::Entered(Direction::Right),
+                EventKind::Within {
+                    axis: Axis::Vertical
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_calibration_json_round_trip() {
+        let calibration = Calibration {
+            horizontal: AxisCalibration {
+                min: 3,
+                center: 131,
+                max: 250,
+                invert: true,
+            },
+            ..Calibration::default()
         };
-        (horizontal, vertical, pressed)
+        assert_eq!(
+            Calibration::from_json(&calibration.to_json()).unwrap(),
+            calibration
+        );
     }
 }
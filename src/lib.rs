@@ -1,12 +1,32 @@
 pub mod adc_0832;
+pub mod adc_sampler;
+pub mod altimetry;
+pub mod analog;
 pub mod barometer;
+pub mod bme280;
+pub mod config;
 pub mod distance;
 pub mod dot_matrix;
+pub mod filter;
 pub mod huffman_code;
 pub mod joy_stick;
 pub mod lasers;
 pub mod lcd;
+pub mod menu;
 pub mod motor;
+pub mod pcf8591;
+pub mod pid;
+pub mod pressure_filter;
+pub mod psychrometrics;
+pub mod pwm;
+pub mod ring;
 pub mod segment;
+pub mod servo;
+pub mod stepper;
+pub mod tachometer;
 pub mod temp;
 pub mod temp_humid;
+#[cfg(test)]
+mod test_support;
+pub mod transducer;
+pub mod worker;
@@ -1,46 +1,109 @@
-use pi_play_lib::barometer::{Barometer, Mode::HighRes};
+use pi_play_lib::altimetry::do_variometer;
+use pi_play_lib::barometer::{detect, Barometer};
+use pi_play_lib::config::{Config, CONFIG_PATH};
 use pi_play_lib::dot_matrix::{DotMatrix, DotMatrixData};
 use pi_play_lib::lcd::LCD;
-use pi_play_lib::temp_humid::measure_temp_humid;
+use pi_play_lib::menu::do_menu;
+use pi_play_lib::pid::{do_fan, TemperatureSource};
+use pi_play_lib::pressure_filter::{Continuous, Filter, Filtered};
+use pi_play_lib::psychrometrics::Comfort;
+use pi_play_lib::temp_humid::{Dht, Model::Dht11};
+use std::env;
 use std::thread;
 use std::time::Duration;
 
 fn main() {
+    // Saved from the menu; --qnh=<hPa>, --celsius and --fahrenheit override for this run.
+    let config = Config::load(CONFIG_PATH).with_args(env::args());
+
+    // Cycle dew point, heat index etc. on the LCD after the main readings.
+    let show_comfort = env::args().any(|arg| arg == "--comfort");
+
+    // Altitude and vertical speed instead of the weather station.
+    // --beep adds the buzzer.
+    if env::args().any(|arg| arg == "--vario") {
+        do_variometer(&config, env::args().any(|arg| arg == "--beep"));
+        return;
+    }
+
+    // Cool the enclosure to --fan=<celsius> with the motor as a fan.
+    // Reads the 1-wire sensor, or the pressure sensor with --fan-barometer.
+    if let Some(setpoint) = env::args().find_map(|arg| {
+        arg.strip_prefix("--fan=")
+            .map(|c| c.parse::<f32>().expect("Setpoint should be in celsius"))
+    }) {
+        let source = match env::args().any(|arg| arg == "--fan-barometer") {
+            true => TemperatureSource::Barometer,
+            false => TemperatureSource::OneWire,
+        };
+        do_fan(setpoint, source, config.temperature_unit);
+        return;
+    }
+
+    // Set up the station from the joystick and LCD.
+    if env::args().any(|arg| arg == "--menu") {
+        do_menu();
+        return;
+    }
+
+    // Print the BMP085 EEPROM and a raw reading as JSON for bug reports, valid or not.
+    if env::args().any(|arg| arg == "--dump-barometer") {
+        let mut barometer = Barometer::new();
+        println!("{}", barometer.dump().to_json());
+        return;
+    }
+
     let mut dot_matrix = DotMatrix::new();
 
     let mut lcd = LCD::new();
     lcd.display_init();
+    if !config.backlight {
+        lcd.backlight_off();
+    }
+
+    // BMP085/180, BMP280 or BME280, averaged and smoothed in the background.
+    let sensor = detect().expect("Pressure sensor should be found");
+    let barometer = Continuous::spawn(
+        Filtered::new(sensor, Filter::default()),
+        Duration::from_secs(1),
+    );
 
-    let mut barometer = Barometer::new();
-    barometer.init();
-    let mode = HighRes;
+    let mut dht = Dht::new(Dht11);
 
-    let mut prev_humidity: f32 = 0.0;
+    let mut prev_humidity: Option<f32> = None;
     let mut prev_pressure: i64 = 0;
     let mut prev_temp: i64 = 0;
 
     loop {
-        let raw_c = barometer.read_raw_temp();
-        let celsius = barometer.read_temperature(raw_c);
-        let fahrenheit = ((celsius as f32 / 10_f32) * 9.0_f32 / 5.0) + 32.0;
+        // Tenths of a degree and whole Pa, so the trend arrows ignore noise.
+        let measurement = barometer.latest();
+        let celsius = (measurement.celsius * 10.0).round() as i64;
+        let pressure = measurement.pressure.round() as i64;
 
-        let raw_pressure = barometer.read_raw_pressure(&mode);
-        let pressure = barometer.read_pressure(raw_pressure, &mode);
-        let (_, humidity) = measure_temp_humid();
+        // Fall back on a BME280's humidity without a DHT.
+        let humidity = dht
+            .latest()
+            .map(|(reading, _)| reading.humidity)
+            .or(measurement.humidity);
+        if dht.stats().consecutive_failures > 0 {
+            if let Some(e) = &dht.stats().last_error {
+                println!("\nError reading temp/humidity; {e}.");
+            }
+        }
+        let humidity_str = match humidity {
+            Some(humidity) => format!("{:.1}", humidity),
+            None => "--".to_string(),
+        };
 
         let message = Vec::from([
             format!(
-                "C {:.1} F {:.1}        ",
-                celsius as f32 / 10_f32,
-                fahrenheit
-            ),
-            format!(
-                "B {:.1} H {:.1}        ",
-                pressure as f32 / 100_f32,
-                prev_humidity
+                "{} H {}        ",
+                config.temperature_unit.format(celsius as f32 / 10_f32),
+                humidity_str
             ),
+            format!("B {}        ", config.pressure_unit.format(pressure as f32)),
         ]);
-        lcd.display_data(message);
+        lcd.display_data(message.clone());
 
         let dot_matrix_data = DotMatrixData::new();
         if pressure > prev_pressure {
@@ -63,23 +126,23 @@ fn main() {
             dot_matrix.display_data(&dot_matrix_data.data[4], dot_matrix_data.tab);
             dot_matrix.display_data(&dot_matrix_data.data[0], dot_matrix_data.rev_tab);
         }
-        if humidity > prev_humidity {
-            dot_matrix.display_data(&dot_matrix_data.data[5], dot_matrix_data.tab);
-            dot_matrix.display_data(&dot_matrix_data.data[1], dot_matrix_data.rev_tab);
-        } else if humidity != prev_humidity || humidity == 0.0 {
-            dot_matrix.display_data(&dot_matrix_data.data[5], dot_matrix_data.tab);
-            dot_matrix.display_data(&dot_matrix_data.data[2], dot_matrix_data.rev_tab);
-        } else {
-            dot_matrix.display_data(&dot_matrix_data.data[5], dot_matrix_data.tab);
-            dot_matrix.display_data(&dot_matrix_data.data[0], dot_matrix_data.rev_tab);
+        match (humidity, prev_humidity) {
+            (Some(humidity), Some(prev)) if humidity > prev => {
+                dot_matrix.display_data(&dot_matrix_data.data[5], dot_matrix_data.tab);
+                dot_matrix.display_data(&dot_matrix_data.data[1], dot_matrix_data.rev_tab);
+            }
+            (Some(humidity), Some(prev)) if humidity < prev => {
+                dot_matrix.display_data(&dot_matrix_data.data[5], dot_matrix_data.tab);
+                dot_matrix.display_data(&dot_matrix_data.data[0], dot_matrix_data.rev_tab);
+            }
+            _ => {
+                dot_matrix.display_data(&dot_matrix_data.data[5], dot_matrix_data.tab);
+                dot_matrix.display_data(&dot_matrix_data.data[2], dot_matrix_data.rev_tab);
+            }
         }
 
         dot_matrix.display_data(&dot_matrix_data.data[6], dot_matrix_data.tab);
-        prev_humidity = if humidity != prev_humidity && humidity != 0.0 {
-            humidity
-        } else {
-            prev_humidity
-        };
+        prev_humidity = humidity.or(prev_humidity);
         prev_temp = if celsius != prev_temp {
             celsius
         } else {
@@ -91,6 +154,16 @@ fn main() {
             prev_pressure
         };
 
+        if let (true, Some(humidity)) = (show_comfort, humidity) {
+            let comfort = Comfort::new(celsius as f32 / 10_f32, humidity, pressure as f32);
+            for page in comfort.lcd_pages(config.temperature_unit) {
+                thread::sleep(Duration::from_secs(3));
+                lcd.display_data(page);
+            }
+            thread::sleep(Duration::from_secs(3));
+            lcd.display_data(message);
+        }
+
         thread::sleep(Duration::from_secs(15))
     }
 }
@@ -0,0 +1,402 @@
+// Settings menu on the 16x2 LCD, driven by the joystick.
+//
+// Up and down move, press selects, left goes back to the main list.
+// Changes are saved to the config file as they are made.
+
+use crate::altimetry::{altitude, qnh_from_qfe};
+use crate::barometer::detect;
+use crate::config::{Config, CONFIG_PATH};
+use crate::huffman_code::HuffTree;
+use crate::joy_stick::{
+    Calibration, Direction, EventKind, Events, JoyStick, Settings, CALIBRATION_PATH,
+};
+use crate::lasers::Laser;
+use crate::lcd::LCD;
+use crate::motor::Motor;
+use crate::pressure_filter::{Continuous, Filter, Filtered};
+use crate::temp_humid::{Dht, Model::Dht11};
+use std::thread;
+use std::time::Duration;
+
+// Steps for the up/down adjustments.
+const QNH_STEP: f32 = 10.0;
+const ELEVATION_STEP: f32 = 10.0;
+
+// QNH is kept within the extremes ever recorded at sea level, in Pa.
+const QNH_MIN: f32 = 87_000.0;
+const QNH_MAX: f32 = 108_500.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+}
+
+impl Key {
+    /// Straight directions and the button; diagonals are ignored.
+    pub fn from_event(kind: EventKind) -> Option<Key> {
+        match kind {
+            EventKind::Entered(Direction::Up) => Some(Key::Up),
+            EventKind::Entered(Direction::Down) => Some(Key::Down),
+            EventKind::Entered(Direction::Left) => Some(Key::Left),
+            EventKind::Entered(Direction::Right) => Some(Key::Right),
+            EventKind::Pressed => Some(Key::Select),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Screen {
+    Main,
+    Sensors,
+    Units,
+    Altimeter,
+    Backlight,
+    Actuators,
+}
+
+const MAIN: [(&str, Screen); 5] = [
+    ("Sensors", Screen::Sensors),
+    ("Units", Screen::Units),
+    ("QNH/Altitude", Screen::Altimeter),
+    ("Backlight", Screen::Backlight),
+    ("Motor/Laser", Screen::Actuators),
+];
+
+/// Entries, or pages, on each screen.
+fn entries(screen: Screen) -> usize {
+    match screen {
+        Screen::Main => MAIN.len(),
+        Screen::Sensors => 4,
+        Screen::Units | Screen::Altimeter | Screen::Actuators => 2,
+        Screen::Backlight => 1,
+    }
+}
+
+/// Hardware the menu asks the caller to drive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Backlight(bool),
+    RunMotor,
+    RunLaser,
+}
+
+/// Latest sensor values to show. Celsius, Pa and percent.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Readings {
+    pub celsius: Option<f32>,
+    pub pressure: Option<f32>,
+    pub humidity: Option<f32>,
+}
+
+pub struct Menu {
+    pub config: Config,
+    screen: Screen,
+    // Position on the main list, kept while inside a screen.
+    main_cursor: usize,
+    cursor: usize,
+}
+
+impl Menu {
+    pub fn new(config: Config) -> Menu {
+        Menu {
+            config,
+            screen: Screen::Main,
+            main_cursor: 0,
+            cursor: 0,
+        }
+    }
+
+    pub fn screen(&self) -> Screen {
+        self.screen
+    }
+
+    fn step(cursor: usize, count: usize, key: Key) -> usize {
+        match key {
+            Key::Up => (cursor + count - 1) % count,
+            _ => (cursor + 1) % count,
+        }
+    }
+
+    /// Apply a key, changing `config` as needed.
+    pub fn handle(&mut self, key: Key, readings: &Readings) -> Option<Command> {
+        if self.screen == Screen::Main {
+            match key {
+                Key::Up | Key::Down => {
+                    self.main_cursor = Menu::step(self.main_cursor, MAIN.len(), key)
+                }
+                Key::Select | Key::Right => {
+                    self.screen = MAIN[self.main_cursor].1;
+                    self.cursor = 0;
+                }
+                Key::Left => {}
+            }
+            return None;
+        }
+        if key == Key::Left {
+            self.screen = Screen::Main;
+            return None;
+        }
+        match (self.screen, key) {
+            (Screen::Altimeter, Key::Up | Key::Down) => {
+                let sign = if key == Key::Up { 1.0 } else { -1.0 };
+                self.adjust_altimeter(sign, readings);
+            }
+            (_, Key::Up | Key::Down) => {
+                self.cursor = Menu::step(self.cursor, entries(self.screen), key)
+            }
+            (Screen::Units, _) => match self.cursor {
+                0 => self.config.temperature_unit = self.config.temperature_unit.next(),
+                _ => self.config.pressure_unit = self.config.pressure_unit.next(),
+            },
+            // Select or right switches between the QNH and elevation fields.
+            (Screen::Altimeter, _) => self.cursor = 1 - self.cursor,
+            (Screen::Backlight, _) => {
+                self.config.backlight = !self.config.backlight;
+                return Some(Command::Backlight(self.config.backlight));
+            }
+            (Screen::Actuators, _) => {
+                return Some(match self.cursor {
+                    0 => Command::RunMotor,
+                    _ => Command::RunLaser,
+                })
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Nudge QNH, or the elevation and with it QNH from the current pressure.
+    fn adjust_altimeter(&mut self, sign: f32, readings: &Readings) {
+        if self.cursor == 0 {
+            self.config.qnh = (self.config.qnh + sign * QNH_STEP).clamp(QNH_MIN, QNH_MAX);
+            return;
+        }
+        let Some(pressure) = readings.pressure else {
+            return;
+        };
+        let current = self.config.elevation.unwrap_or_else(|| {
+            (altitude(pressure, self.config.qnh) / ELEVATION_STEP).round() * ELEVATION_STEP
+        });
+        let elevation = current + sign * ELEVATION_STEP;
+        let qnh = qnh_from_qfe(pressure, elevation);
+        if !(QNH_MIN..=QNH_MAX).contains(&qnh) {
+            return;
+        }
+        self.config.elevation = Some(elevation);
+        self.config.qnh = qnh;
+    }
+
+    /// Two lines of at most 16 characters.
+    pub fn render(&self, readings: &Readings) -> Vec<String> {
+        let marker = |i: usize| if i == self.cursor { '>' } else { ' ' };
+        let missing = || "--".to_string();
+        match self.screen {
+            Screen::Main => Vec::from([
+                format!(">{}", MAIN[self.main_cursor].0),
+                format!(" {}", MAIN[(self.main_cursor + 1) % MAIN.len()].0),
+            ]),
+            Screen::Sensors => {
+                let (title, value) = match self.cursor {
+                    0 => (
+                        "Temperature",
+                        readings
+                            .celsius
+                            .map(|c| self.config.temperature_unit.format(c)),
+                    ),
+                    1 => (
+                        "Pressure",
+                        readings
+                            .pressure
+                            .map(|p| self.config.pressure_unit.format(p)),
+                    ),
+                    2 => ("Humidity", readings.humidity.map(|h| format!("{:.1} %", h))),
+                    _ => (
+                        "Altitude",
+                        readings
+                            .pressure
+                            .map(|p| format!("{:.0} m", altitude(p, self.config.qnh))),
+                    ),
+                };
+                Vec::from([title.to_string(), value.unwrap_or_else(missing)])
+            }
+            Screen::Units => Vec::from([
+                format!(
+                    "{}Temp {}",
+                    marker(0),
+                    self.config.temperature_unit.symbol()
+                ),
+                format!("{}Pres {}", marker(1), self.config.pressure_unit.symbol()),
+            ]),
+            Screen::Altimeter => Vec::from([
+                format!("{}QNH {:.1} hPa", marker(0), self.config.qnh / 100.0),
+                format!(
+                    "{}Elev {}",
+                    marker(1),
+                    self.config
+                        .elevation
+                        .map(|e| format!("{:.0} m", e))
+                        .unwrap_or_else(missing)
+                ),
+            ]),
+            Screen::Backlight => Vec::from([
+                "Backlight".to_string(),
+                format!(">{}", if self.config.backlight { "On" } else { "Off" }),
+            ]),
+            Screen::Actuators => Vec::from([
+                format!("{}Run motor", marker(0)),
+                format!("{}Fire laser", marker(1)),
+            ]),
+        }
+    }
+}
+
+/// Run the menu until the process is stopped.
+pub fn do_menu() {
+    let mut lcd = LCD::new();
+    lcd.display_init();
+    let mut menu = Menu::new(Config::load(CONFIG_PATH));
+    if !menu.config.backlight {
+        lcd.backlight_off();
+    }
+
+    let mut joy_stick = JoyStick::new();
+    // Uncalibrated, the axes assume the full 0-255 travel centred on 128.
+    joy_stick.set_calibration(Calibration::load(CALIBRATION_PATH).unwrap_or_default());
+    let events = Events::spawn(joy_stick, Settings::default());
+
+    let barometer = Continuous::spawn(
+        Filtered::new(
+            detect().expect("Pressure sensor should be found"),
+            Filter::default(),
+        ),
+        Duration::from_secs(1),
+    );
+    let mut dht = Dht::new(Dht11);
+    let mut motor = Motor::new();
+
+    loop {
+        let measurement = barometer.latest();
+        // Only touches the sensor once its pacing window has passed, so a missing DHT
+        // doesn't hold up the joystick.
+        let readings = Readings {
+            celsius: Some(measurement.celsius),
+            pressure: Some(measurement.pressure),
+            humidity: dht
+                .latest()
+                .map(|(reading, _)| reading.humidity)
+                .or(measurement.humidity),
+        };
+        // Redraw at least once a second so sensor pages stay live.
+        if let Ok(event) = events.receiver().recv_timeout(Duration::from_secs(1)) {
+            if let Some(key) = Key::from_event(event.kind) {
+                let before = menu.config;
+                match menu.handle(key, &readings) {
+                    Some(Command::Backlight(true)) => lcd.backlight_on(),
+                    Some(Command::Backlight(false)) => lcd.backlight_off(),
+                    Some(Command::RunMotor) => {
+                        motor.start();
+                        thread::sleep(Duration::from_secs(2));
+                        motor.stop();
+                    }
+                    Some(Command::RunLaser) => {
+                        let mut huff_tree = HuffTree::new();
+                        Laser::new(huff_tree.encode("Hello".to_string())).send_message();
+                    }
+                    None => {}
+                }
+                if menu.config != before {
+                    if let Err(e) = menu.config.save(CONFIG_PATH) {
+                        println!("\nError saving config; {e}.");
+                    }
+                }
+            }
+        }
+        lcd.display_data(menu.render(&readings));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn readings() -> Readings {
+        Readings {
+            celsius: Some(21.5),
+            pressure: Some(95_000.0),
+            humidity: None,
+        }
+    }
+
+    #[test]
+    fn test_navigate_and_change_units() {
+        let mut menu = Menu::new(Config::default());
+        let readings = readings();
+        assert_eq!(menu.handle(Key::Down, &readings), None);
+        menu.handle(Key::Select, &readings);
+        assert_eq!(menu.screen(), Screen::Units);
+        menu.handle(Key::Down, &readings);
+        menu.handle(Key::Select, &readings);
+        assert_eq!(menu.render(&readings), vec![" Temp C", ">Pres inHg"]);
+        menu.handle(Key::Left, &readings);
+        assert_eq!(menu.screen(), Screen::Main);
+        assert_eq!(menu.render(&readings), vec![">Units", " QNH/Altitude"]);
+    }
+
+    #[test]
+    fn test_elevation_sets_qnh() {
+        let mut menu = Menu::new(Config::default());
+        let readings = readings();
+        menu.handle(Key::Up, &readings);
+        menu.handle(Key::Up, &readings);
+        menu.handle(Key::Up, &readings);
+        menu.handle(Key::Select, &readings);
+        assert_eq!(menu.screen(), Screen::Altimeter);
+        menu.handle(Key::Select, &readings);
+        menu.handle(Key::Up, &readings);
+        let elevation = menu.config.elevation.unwrap();
+        assert!((altitude(95_000.0, menu.config.qnh) - elevation).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_qnh_stays_plausible() {
+        let mut menu = Menu::new(Config::default());
+        let readings = readings();
+        menu.screen = Screen::Altimeter;
+        for _ in 0..2000 {
+            menu.handle(Key::Down, &readings);
+        }
+        assert_eq!(menu.config.qnh, QNH_MIN);
+        for _ in 0..4000 {
+            menu.handle(Key::Up, &readings);
+        }
+        assert_eq!(menu.config.qnh, QNH_MAX);
+    }
+
+    #[test]
+    fn test_screens_fit_lcd() {
+        let mut config = Config::default();
+        config.elevation = Some(-400.0);
+        config.pressure_unit = crate::config::PressureUnit::MmHg;
+        config.temperature_unit = crate::config::TemperatureUnit::Fahrenheit;
+        let readings = Readings {
+            celsius: Some(-40.0),
+            pressure: Some(108_000.0),
+            humidity: Some(100.0),
+        };
+        for (_, screen) in MAIN {
+            for cursor in 0..entries(screen) {
+                let mut menu = Menu::new(config);
+                menu.screen = screen;
+                menu.cursor = cursor;
+                for line in menu.render(&readings) {
+                    assert!(line.len() <= 16, "{line} is too long");
+                }
+            }
+        }
+    }
+}
@@ -1,31 +1,325 @@
+// DC motor on an L293D or L9110 H-bridge. PWM on `in_` sets the speed, `out` the direction.
+//
+// in_  out
+//  1    0   forward
+//  0    1   reverse
+//  1    1   brake, both terminals shorted through the bridge
+//  0    0   coast, both terminals floating
+//
+// Reverse drives `in_` with the inverted duty, so the off part of each period brakes
+// instead of coasting; one PWM channel is enough for both directions.
+//
+// let mut motor = Motor::new();
+// motor.set_acceleration(1.0)?; // full speed in a second
+// motor.forward(128);
+// motor.reverse(255); // ramps down, pauses, then ramps up the other way
+
+use crate::pwm::{Duty, Pwm, SoftPwm, SysfsPwm};
 use gpio::GpioOut;
+use std::fmt;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+const IN_PIN: u16 = 5;
+const OUT_PIN: u16 = 6;
+
+// Fast enough not to judder a small motor, slow enough for software PWM.
+const FREQUENCY: f32 = 200.0;
+
+// Time between ramp steps.
+const RAMP_STEP: Duration = Duration::from_millis(10);
+
+// Pause with the bridge off before driving the other way.
+const DEAD_TIME: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Drive {
+    Forward(Duty),
+    Reverse(Duty),
+    Brake,
+    Coast,
+}
+
+impl Drive {
+    /// -1.0 full reverse to 1.0 full forward; zero coasts.
+    pub fn from_velocity(velocity: f32) -> Drive {
+        if velocity > 0.0 {
+            Drive::Forward(Duty::new(velocity))
+        } else if velocity < 0.0 {
+            Drive::Reverse(Duty::new(-velocity))
+        } else {
+            Drive::Coast
+        }
+    }
+
+    /// Duty for `in_` and level for `out`.
+    pub fn outputs(&self) -> (Duty, bool) {
+        match self {
+            Drive::Forward(duty) => (*duty, false),
+            Drive::Reverse(duty) => (duty.inverted(), true),
+            Drive::Brake => (Duty::FULL, true),
+            Drive::Coast => (Duty::OFF, false),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MotorError {
+    /// Zero, negative or NaN; the ramp would never arrive.
+    BadAcceleration(f32),
+}
+
+impl fmt::Display for MotorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MotorError::BadAcceleration(acceleration) => {
+                write!(f, "acceleration {acceleration} is not positive")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MotorError {}
+
+/// Limits how fast the velocity changes, and never lets it pass through zero in one step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ramp {
+    acceleration: f32,
+    velocity: f32,
+}
+
+impl Ramp {
+    /// Change in velocity per second; full speed is 1.0. Infinite jumps straight there.
+    pub fn new(acceleration: f32) -> Result<Ramp, MotorError> {
+        let mut ramp = Ramp {
+            acceleration: f32::INFINITY,
+            velocity: 0.0,
+        };
+        ramp.set_acceleration(acceleration)?;
+        Ok(ramp)
+    }
+
+    pub fn acceleration(&self) -> f32 {
+        self.acceleration
+    }
+
+    pub fn set_acceleration(&mut self, acceleration: f32) -> Result<(), MotorError> {
+        if acceleration.is_nan() || acceleration <= 0.0 {
+            return Err(MotorError::BadAcceleration(acceleration));
+        }
+        self.acceleration = acceleration;
+        Ok(())
+    }
+
+    pub fn velocity(&self) -> f32 {
+        self.velocity
+    }
+
+    /// Move towards `target` for `elapsed`, stopping at zero when it has the other sign.
+    /// A NaN target is ignored.
+    pub fn step(&mut self, target: f32, elapsed: Duration) -> f32 {
+        if target.is_nan() {
+            return self.velocity;
+        }
+        let target = target.clamp(-1.0, 1.0);
+        let target = match self.velocity * target < 0.0 {
+            true => 0.0,
+            false => target,
+        };
+        let limit = self.acceleration * elapsed.as_secs_f32();
+        // Land exactly on the target so callers can compare against it.
+        self.velocity = match (target - self.velocity).abs() <= limit {
+            true => target,
+            false => self.velocity + (target - self.velocity).clamp(-limit, limit),
+        };
+        self.velocity
+    }
+
+    /// Forget the velocity, e.g. after braking.
+    pub fn reset(&mut self) {
+        self.velocity = 0.0;
+    }
+}
 
 pub struct Motor {
-    in_: gpio::sysfs::SysFsGpioOutput,
+    in_: Box<dyn Pwm + Send>,
     out: gpio::sysfs::SysFsGpioOutput,
+    ramp: Ramp,
 }
 
 impl Motor {
+    /// Software PWM on the `in_` pin.
     pub fn new() -> Motor {
-        let mut in_ = gpio::sysfs::SysFsGpioOutput::open(5).unwrap();
-        let mut out = gpio::sysfs::SysFsGpioOutput::open(6).unwrap();
-        in_.set_value(false).unwrap();
-        out.set_value(false).unwrap();
-        Self { in_, out }
+        let in_ = gpio::sysfs::SysFsGpioOutput::open(IN_PIN).unwrap();
+        let pwm = SoftPwm::spawn(in_, FREQUENCY).expect("Frequency should be valid");
+        Motor::with_pwm(Box::new(pwm))
+    }
+
+    /// Hardware PWM from /sys/class/pwm, with the driver's `in_` input wired to that
+    /// channel's pin instead of GPIO 5.
+    pub fn with_sysfs_pwm(chip: u32, channel: u32) -> Motor {
+        let pwm = SysfsPwm::new(chip, channel, FREQUENCY).expect("PWM channel should export");
+        Motor::with_pwm(Box::new(pwm))
+    }
+
+    pub fn with_pwm(in_: Box<dyn Pwm + Send>) -> Motor {
+        let out = gpio::sysfs::SysFsGpioOutput::open(OUT_PIN).unwrap();
+        let mut motor = Self {
+            in_,
+            out,
+            ramp: Ramp::new(f32::INFINITY).expect("Acceleration should be valid"),
+        };
+        motor.apply(Drive::Coast);
+        motor
+    }
+
+    /// Fraction of full speed gained or lost per second. Defaults to no ramp.
+    pub fn set_acceleration(&mut self, acceleration: f32) -> Result<(), MotorError> {
+        self.ramp.set_acceleration(acceleration)
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) -> io::Result<()> {
+        self.in_.set_frequency(frequency)
+    }
+
+    /// -1.0 to 1.0, as last set.
+    pub fn velocity(&self) -> f32 {
+        self.ramp.velocity()
+    }
+
+    /// Set the pins directly. No ramp and no guard, so only switch direction via Coast.
+    fn apply(&mut self, drive: Drive) {
+        let (duty, out) = drive.outputs();
+        // Lower whichever side is going low first so both never pull against each other.
+        match out {
+            true => {
+                self.in_.set_duty(Duty::OFF);
+                self.out.set_value(true).unwrap();
+                self.in_.set_duty(duty);
+            }
+            false => {
+                self.out.set_value(false).unwrap();
+                self.in_.set_duty(duty);
+            }
+        }
+    }
+
+    /// Ramp to `velocity`, blocking until it is reached. Reversing ramps down and coasts for
+    /// a dead time first. NaN leaves the motor as it is.
+    pub fn ramp_to(&mut self, velocity: f32) {
+        if velocity.is_nan() {
+            return;
+        }
+        loop {
+            let before = self.ramp.velocity();
+            let after = match self.ramp.acceleration.is_finite() {
+                true => self.ramp.step(velocity, RAMP_STEP),
+                false => self.ramp.step(velocity, Duration::from_secs(1)),
+            };
+            self.apply(Drive::from_velocity(after));
+            if before != 0.0 && after == 0.0 && velocity != 0.0 {
+                thread::sleep(DEAD_TIME);
+            }
+            if after == velocity.clamp(-1.0, 1.0) {
+                break;
+            }
+            if self.ramp.acceleration.is_finite() {
+                thread::sleep(RAMP_STEP);
+            }
+        }
+    }
+
+    /// 0 stopped through 255 full speed.
+    pub fn forward(&mut self, speed: u8) {
+        self.ramp_to(Duty::from_speed(speed).fraction());
+    }
+
+    pub fn reverse(&mut self, speed: u8) {
+        self.ramp_to(-Duty::from_speed(speed).fraction());
+    }
+
+    /// Stop hard. The bridge shorts the motor, so there is no ramp.
+    pub fn brake(&mut self) {
+        self.apply(Drive::Brake);
+        self.ramp.reset();
+    }
+
+    /// Let the motor spin down on its own.
+    pub fn coast(&mut self) {
+        self.apply(Drive::Coast);
+        self.ramp.reset();
     }
 
     pub fn start(&mut self) {
-        self.in_.set_value(true).unwrap();
-        self.out.set_value(false).unwrap();
+        self.ramp_to(1.0);
     }
 
+    /// Ramp down to a stop.
     pub fn stop(&mut self) {
-        self.in_.set_value(false).unwrap();
-        self.out.set_value(false).unwrap();
+        self.ramp_to(0.0);
     }
 
+    /// Forward; 0 stopped through 255 full speed.
     pub fn run(&mut self, speed: u8) {
-        self.in_.set_value(speed).unwrap();
-        self.out.set_value(false).unwrap();
+        self.forward(speed);
+    }
+
+    /// Forward, or reverse when negative.
+    pub fn run_percent(&mut self, percent: f32) {
+        self.ramp_to(percent / 100.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drive_outputs() {
+        assert_eq!(
+            Drive::from_velocity(0.25).outputs(),
+            (Duty::new(0.25), false)
+        );
+        // Reverse at a quarter: in_ high three quarters of the time, braking.
+        assert_eq!(
+            Drive::from_velocity(-0.25).outputs(),
+            (Duty::new(0.75), true)
+        );
+        assert_eq!(Drive::Brake.outputs(), (Duty::FULL, true));
+        assert_eq!(Drive::from_velocity(0.0).outputs(), (Duty::OFF, false));
+    }
+
+    #[test]
+    fn test_ramp_limits_acceleration() {
+        let mut ramp = Ramp::new(2.0).unwrap();
+        let step = Duration::from_millis(100);
+        assert!((ramp.step(1.0, step) - 0.2).abs() < 1e-6);
+        for _ in 0..10 {
+            ramp.step(1.0, step);
+        }
+        assert_eq!(ramp.velocity(), 1.0);
+    }
+
+    #[test]
+    fn test_ramp_stops_before_reversing() {
+        let mut ramp = Ramp::new(f32::INFINITY).unwrap();
+        ramp.step(0.5, RAMP_STEP);
+        // Even an instant ramp halts at zero on the way to the other direction.
+        assert_eq!(ramp.step(-0.5, RAMP_STEP), 0.0);
+        assert_eq!(ramp.step(-0.5, RAMP_STEP), -0.5);
+    }
+
+    #[test]
+    fn test_ramp_rejects_bad_input() {
+        for acceleration in [0.0, -1.0, f32::NAN] {
+            assert!(matches!(
+                Ramp::new(acceleration),
+                Err(MotorError::BadAcceleration(_))
+            ));
+        }
+        let mut ramp = Ramp::new(1.0).unwrap();
+        ramp.step(0.5, Duration::from_secs(1));
+        assert_eq!(ramp.step(f32::NAN, Duration::from_secs(1)), 0.5);
     }
 }
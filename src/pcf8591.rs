@@ -0,0 +1,227 @@
+// PCF8591 8 bit I2C ADC with four inputs and one DAC output.
+// Datasheet: https://www.nxp.com/docs/en/data-sheet/PCF8591.pdf
+//
+// let mut adc = Pcf8591::new();
+// adc.init();
+// adc.read(Input::SingleEnded(0));
+// adc.set_dac(128);
+
+extern crate i2c_linux;
+
+use crate::analog::{AdcError, AnalogInput, Input};
+use i2c_linux::I2c;
+use std::fs::File;
+
+const ADDR: u16 = 0x48;
+const CHANNELS: u8 = 4;
+
+// Control byte.
+const ANALOG_OUTPUT_ENABLE: u8 = 0x40;
+const AUTO_INCREMENT: u8 = 0x04;
+
+/// Input programming, bits 4 and 5 of the control byte. Channel numbers count from 0 per mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputMode {
+    /// AIN0 through AIN3 against ground.
+    SingleEnded,
+    /// AIN0, AIN1 and AIN2 each against AIN3.
+    ThreeDifferential,
+    /// AIN0 and AIN1 against ground, then AIN2 against AIN3.
+    Mixed,
+    /// AIN0 against AIN1, then AIN2 against AIN3.
+    TwoDifferential,
+}
+
+impl InputMode {
+    fn bits(&self) -> u8 {
+        match self {
+            InputMode::SingleEnded => 0,
+            InputMode::ThreeDifferential => 1,
+            InputMode::Mixed => 2,
+            InputMode::TwoDifferential => 3,
+        }
+    }
+
+    pub fn channels(&self) -> u8 {
+        match self {
+            InputMode::SingleEnded => 4,
+            InputMode::ThreeDifferential | InputMode::Mixed => 3,
+            InputMode::TwoDifferential => 2,
+        }
+    }
+
+    /// Whether `channel` in this mode is a difference, read as two's complement.
+    pub fn is_differential(&self, channel: u8) -> bool {
+        match self {
+            InputMode::SingleEnded => false,
+            InputMode::ThreeDifferential | InputMode::TwoDifferential => true,
+            InputMode::Mixed => channel == 2,
+        }
+    }
+}
+
+/// Mode and channel for an `Input`. Only the positive side of each pair is available.
+pub fn input_mode(input: Input) -> Result<(InputMode, u8), AdcError> {
+    match input {
+        Input::SingleEnded(channel) if channel < CHANNELS => Ok((InputMode::SingleEnded, channel)),
+        Input::Differential(0) => Ok((InputMode::TwoDifferential, 0)),
+        Input::Differential(2) => Ok((InputMode::TwoDifferential, 1)),
+        Input::SingleEnded(channel) | Input::Differential(channel) => Err(AdcError::BadChannel {
+            channel,
+            channels: CHANNELS,
+        }),
+    }
+}
+
+/// Control byte for a read, or for a DAC write with the output enabled.
+pub fn control_byte(mode: InputMode, channel: u8, auto_increment: bool, dac: bool) -> u8 {
+    let mut control = (mode.bits() << 4) | (channel & 0x03);
+    if auto_increment {
+        control |= AUTO_INCREMENT;
+    }
+    if dac {
+        control |= ANALOG_OUTPUT_ENABLE;
+    }
+    control
+}
+
+pub struct Pcf8591 {
+    i2c: I2c<File>,
+    addr: u16,
+    vref: f32,
+    // Leaving the analog output bit clear on a read would switch the DAC off.
+    dac: bool,
+}
+
+impl Default for Pcf8591 {
+    fn default() -> Self {
+        Pcf8591::new()
+    }
+}
+
+impl Pcf8591 {
+    pub fn new() -> Pcf8591 {
+        Pcf8591::with_addr(ADDR)
+    }
+
+    /// A0 through A2 strapped high add 1 to 7 to 0x48.
+    pub fn with_addr(addr: u16) -> Pcf8591 {
+        let i2c = I2c::from_path("/dev/i2c-1").expect("Device should be found");
+        Self {
+            i2c,
+            addr,
+            vref: 3.3,
+            dac: false,
+        }
+    }
+
+    /// Reference voltage, full scale for reads and the DAC. Defaults to the 3.3 V rail.
+    pub fn with_vref(mut self, vref: f32) -> Pcf8591 {
+        self.vref = vref;
+        self
+    }
+
+    pub fn init(&mut self) {
+        self.i2c
+            .smbus_set_slave_address(self.addr, false)
+            .expect("Slave addr should be set");
+    }
+
+    /// Raw byte for `channel` of `mode`. Differential channels are two's complement.
+    pub fn read_raw(&mut self, mode: InputMode, channel: u8) -> Result<u8, AdcError> {
+        if channel >= mode.channels() {
+            return Err(AdcError::BadChannel {
+                channel,
+                channels: mode.channels(),
+            });
+        }
+        self.i2c
+            .smbus_write_byte(control_byte(mode, channel, false, self.dac))
+            .expect("data should write");
+        // The first byte is the conversion made during the previous read.
+        self.i2c.smbus_read_byte().expect("data should read");
+        Ok(self.i2c.smbus_read_byte().expect("data should read"))
+    }
+
+    /// Signed difference for a differential channel, -128 to 127.
+    pub fn read_signed(&mut self, mode: InputMode, channel: u8) -> Result<i8, AdcError> {
+        Ok(self.read_raw(mode, channel)? as i8)
+    }
+
+    /// Every channel of `mode` in one auto-incrementing pass.
+    pub fn read_all(&mut self, mode: InputMode) -> Vec<u8> {
+        self.i2c
+            .smbus_write_byte(control_byte(mode, 0, true, self.dac))
+            .expect("data should write");
+        self.i2c.smbus_read_byte().expect("data should read");
+        (0..mode.channels())
+            .map(|_| self.i2c.smbus_read_byte().expect("data should read"))
+            .collect()
+    }
+
+    /// Drive AOUT to `value` / 256 of `vref` and keep it on.
+    pub fn set_dac(&mut self, value: u8) {
+        self.dac = true;
+        self.i2c
+            .smbus_write_byte_data(control_byte(InputMode::SingleEnded, 0, false, true), value)
+            .expect("data should write");
+    }
+
+    pub fn set_dac_voltage(&mut self, volts: f32) {
+        let value = (volts / self.vref * 256.0).round().clamp(0.0, 255.0) as u8;
+        self.set_dac(value);
+    }
+
+    /// Switch AOUT off to save the DAC's supply current.
+    pub fn disable_dac(&mut self) {
+        self.dac = false;
+        self.i2c
+            .smbus_write_byte(control_byte(InputMode::SingleEnded, 0, false, false))
+            .expect("data should write");
+    }
+}
+
+impl AnalogInput for Pcf8591 {
+    fn read(&mut self, input: Input) -> Result<u8, AdcError> {
+        let (mode, channel) = input_mode(input)?;
+        let raw = self.read_raw(mode, channel)?;
+        Ok(match mode.is_differential(channel) {
+            // Scale the positive half to the full count, like the ADC0832.
+            true => (raw as i8).max(0) as u8 * 2,
+            false => raw,
+        })
+    }
+
+    fn vref(&self) -> f32 {
+        self.vref
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_byte() {
+        assert_eq!(control_byte(InputMode::SingleEnded, 2, false, false), 0x02);
+        assert_eq!(control_byte(InputMode::SingleEnded, 0, true, true), 0x44);
+        assert_eq!(
+            control_byte(InputMode::TwoDifferential, 1, false, false),
+            0x31
+        );
+    }
+
+    #[test]
+    fn test_input_mode() {
+        assert_eq!(
+            input_mode(Input::SingleEnded(3)),
+            Ok((InputMode::SingleEnded, 3))
+        );
+        assert_eq!(
+            input_mode(Input::Differential(2)),
+            Ok((InputMode::TwoDifferential, 1))
+        );
+        assert!(input_mode(Input::Differential(1)).is_err());
+        assert!(input_mode(Input::SingleEnded(4)).is_err());
+    }
+}
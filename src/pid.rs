@@ -0,0 +1,344 @@
+// PID controller, and a fan holding an enclosure at a set temperature.
+//
+// let mut pid = Pid::new(Gains { kp: 0.2, ki: 0.01, kd: 0.5 }, 30.0)
+//     .reversed()
+//     .with_limits(0.0, 1.0);
+// motor.run_percent(pid.update(celsius, Instant::now()) * 100.0);
+//
+// Derivative on measurement, so setpoint changes don't kick the output:
+// http://brettbeauregard.com/blog/2011/04/improving-the-beginners-pid-derivative-kick/
+
+use crate::barometer::{detect, PressureSensor};
+use crate::config::TemperatureUnit;
+use crate::filter::Iir;
+use crate::motor::Motor;
+use crate::temp::read_temp;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gains {
+    pub kp: f32,
+    /// Per second.
+    pub ki: f32,
+    /// Seconds.
+    pub kd: f32,
+}
+
+pub struct Pid {
+    gains: Gains,
+    setpoint: f32,
+    min: f32,
+    max: f32,
+    sample_time: Duration,
+    // Output rises as the measurement rises, e.g. cooling.
+    reverse: bool,
+    integral: f32,
+    derivative: Iir,
+    last: Option<(f32, Instant)>,
+    output: f32,
+}
+
+impl Pid {
+    pub fn new(gains: Gains, setpoint: f32) -> Pid {
+        Pid {
+            gains,
+            setpoint,
+            min: f32::NEG_INFINITY,
+            max: f32::INFINITY,
+            sample_time: Duration::ZERO,
+            reverse: false,
+            integral: 0.0,
+            derivative: Iir::new(1.0),
+            last: None,
+            output: 0.0,
+        }
+    }
+
+    /// Clamp the output, and the integral with it. Panics unless `min <= max`, so NaN too.
+    pub fn with_limits(mut self, min: f32, max: f32) -> Pid {
+        assert!(
+            min <= max,
+            "PID limits {min} to {max} should have min <= max"
+        );
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    /// Updates sooner than this after the last one return the last output unchanged.
+    pub fn with_sample_time(mut self, sample_time: Duration) -> Pid {
+        self.sample_time = sample_time;
+        self
+    }
+
+    /// Weight of each new derivative in its low pass filter; 1.0, the default, is unfiltered.
+    pub fn with_derivative_filter(mut self, alpha: f32) -> Pid {
+        self.derivative = Iir::new(alpha);
+        self
+    }
+
+    /// Drive the output up when the measurement is above the setpoint.
+    pub fn reversed(mut self) -> Pid {
+        self.reverse = true;
+        self
+    }
+
+    pub fn setpoint(&self) -> f32 {
+        self.setpoint
+    }
+
+    pub fn set_setpoint(&mut self, setpoint: f32) {
+        self.setpoint = setpoint;
+    }
+
+    pub fn set_gains(&mut self, gains: Gains) {
+        self.gains = gains;
+    }
+
+    /// Forget the integral and history, e.g. after the loop was switched off.
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.derivative.reset();
+        self.last = None;
+        self.output = 0.0;
+    }
+
+    /// A NaN measurement, or one no later than the last, returns the last output unchanged.
+    pub fn update(&mut self, measurement: f32, at: Instant) -> f32 {
+        if measurement.is_nan() {
+            return self.output;
+        }
+        let (error, rate) = match self.last {
+            // No time between samples would make the derivative infinite.
+            Some((_, last_at)) if at <= last_at || at - last_at < self.sample_time => {
+                return self.output
+            }
+            Some((last_measurement, last_at)) => {
+                let dt = (at - last_at).as_secs_f32();
+                let rate = self
+                    .derivative
+                    .update((measurement - last_measurement) / dt);
+                (self.setpoint - measurement, Some((rate, dt)))
+            }
+            None => (self.setpoint - measurement, None),
+        };
+        let sign = if self.reverse { -1.0 } else { 1.0 };
+        let error = sign * error;
+
+        let proportional = self.gains.kp * error;
+        let derivative = match rate {
+            Some((rate, _)) => -sign * self.gains.kd * rate,
+            None => 0.0,
+        };
+        if let Some((_, dt)) = rate {
+            // Anti-windup: only integrate while it can still move the output, and never
+            // past the limits on its own.
+            let unclamped = proportional + self.integral + derivative;
+            let saturated =
+                (unclamped >= self.max && error > 0.0) || (unclamped <= self.min && error < 0.0);
+            if !saturated {
+                self.integral =
+                    (self.integral + self.gains.ki * error * dt).clamp(self.min, self.max);
+            }
+        }
+
+        self.output = (proportional + self.integral + derivative).clamp(self.min, self.max);
+        self.last = Some((measurement, at));
+        self.output
+    }
+}
+
+/// Where the fan app reads its temperature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TemperatureSource {
+    /// DS18B20 on the 1-wire bus.
+    OneWire,
+    /// Whichever pressure sensor is found.
+    Barometer,
+}
+
+/// Run the motor as a fan, faster the further the temperature is above `setpoint` celsius.
+pub fn do_fan(setpoint: f32, source: TemperatureSource, unit: TemperatureUnit) {
+    let sample_time = Duration::from_secs(2);
+    let mut motor = Motor::new();
+    motor
+        .set_acceleration(0.5)
+        .expect("Acceleration should be positive");
+    let mut barometer = match source {
+        TemperatureSource::Barometer => Some(detect().expect("Pressure sensor should be found")),
+        TemperatureSource::OneWire => None,
+    };
+    let mut pid = Pid::new(
+        Gains {
+            kp: 0.15,
+            ki: 0.01,
+            kd: 0.3,
+        },
+        setpoint,
+    )
+    .reversed()
+    .with_limits(0.0, 1.0)
+    .with_sample_time(sample_time)
+    .with_derivative_filter(0.3);
+
+    loop {
+        let celsius = match barometer.as_mut() {
+            Some(barometer) => barometer.measure().celsius,
+            None => read_temp(false),
+        };
+        let output = pid.update(celsius, Instant::now());
+        motor.run_percent(output * 100.0);
+        println!("{}, fan {:.0} %", unit.format(celsius), output * 100.0);
+        thread::sleep(sample_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Enclosure with a constant heat source; the fan multiplies its loss to ambient.
+    struct Enclosure {
+        celsius: f32,
+        ambient: f32,
+        /// Degrees above ambient with the fan off.
+        heating: f32,
+        /// Extra loss at full fan, as a multiple of still air.
+        fan_gain: f32,
+        /// Time constant, seconds.
+        tau: f32,
+    }
+
+    impl Enclosure {
+        fn new() -> Enclosure {
+            Enclosure {
+                celsius: 25.0,
+                ambient: 25.0,
+                heating: 20.0,
+                fan_gain: 3.0,
+                tau: 60.0,
+            }
+        }
+
+        fn step(&mut self, fan: f32, dt: f32) {
+            let loss = (self.celsius - self.ambient) * (1.0 + self.fan_gain * fan);
+            self.celsius += (self.heating - loss) / self.tau * dt;
+        }
+    }
+
+    fn fan_pid(setpoint: f32) -> Pid {
+        Pid::new(
+            Gains {
+                kp: 0.15,
+                ki: 0.01,
+                kd: 0.3,
+            },
+            setpoint,
+        )
+        .reversed()
+        .with_limits(0.0, 1.0)
+        .with_derivative_filter(0.3)
+    }
+
+    // Run for `seconds` of one second samples, returning the last output.
+    fn run(pid: &mut Pid, plant: &mut Enclosure, start: Instant, from: u64, seconds: u64) -> f32 {
+        let mut output = 0.0;
+        for t in from..from + seconds {
+            output = pid.update(plant.celsius, start + Duration::from_secs(t));
+            plant.step(output, 1.0);
+        }
+        output
+    }
+
+    #[test]
+    fn test_holds_setpoint_on_simulated_enclosure() {
+        let start = Instant::now();
+        let mut plant = Enclosure::new();
+        let mut pid = fan_pid(35.0);
+        let output = run(&mut pid, &mut plant, start, 0, 1800);
+        assert!((plant.celsius - 35.0).abs() < 0.1, "{}", plant.celsius);
+        // Steady state needs 10 degrees of rise times 1 + 3u to shed 20: u = 1/3.
+        assert!((output - 1.0 / 3.0).abs() < 0.02, "{output}");
+    }
+
+    #[test]
+    fn test_anti_windup_recovers_quickly() {
+        let start = Instant::now();
+        let mut plant = Enclosure::new();
+        // Below ambient: unreachable, so the fan saturates for a long time.
+        let mut pid = fan_pid(20.0);
+        assert_eq!(run(&mut pid, &mut plant, start, 0, 1800), 1.0);
+        pid.set_setpoint(35.0);
+        // A wound up integral would keep the fan running; this one lets it stop at once.
+        assert_eq!(run(&mut pid, &mut plant, start, 1800, 5), 0.0);
+    }
+
+    #[test]
+    fn test_sample_time_and_limits() {
+        let start = Instant::now();
+        let mut pid = Pid::new(
+            Gains {
+                kp: 10.0,
+                ki: 0.0,
+                kd: 0.0,
+            },
+            0.0,
+        )
+        .with_limits(-1.0, 1.0)
+        .with_sample_time(Duration::from_secs(1));
+        assert_eq!(pid.update(-5.0, start), 1.0);
+        // Too soon: unchanged even though the error flipped.
+        assert_eq!(pid.update(5.0, start + Duration::from_millis(500)), 1.0);
+        assert_eq!(pid.update(5.0, start + Duration::from_secs(1)), -1.0);
+    }
+
+    // Measurements 0, 1, 4, 9 one second apart, returning the outputs.
+    fn run_ramp(pid: &mut Pid, start: Instant) -> Vec<f32> {
+        (0..4)
+            .map(|t| pid.update((t * t) as f32, start + Duration::from_secs(t)))
+            .collect()
+    }
+
+    #[test]
+    fn test_repeated_instant_and_reset() {
+        let start = Instant::now();
+        let mut pid = fan_pid(30.0);
+        pid.update(32.0, start);
+        let output = pid.update(33.0, start);
+        assert!(output.is_finite());
+        assert_eq!(pid.update(f32::NAN, start + Duration::from_secs(1)), output);
+
+        // Reset keeps the derivative filter, so it behaves as a fresh controller.
+        let derivative_only = || {
+            Pid::new(
+                Gains {
+                    kp: 0.0,
+                    ki: 0.0,
+                    kd: 1.0,
+                },
+                0.0,
+            )
+            .with_derivative_filter(0.3)
+        };
+        let mut pid = derivative_only();
+        run_ramp(&mut pid, start);
+        pid.reset();
+        assert_eq!(
+            run_ramp(&mut pid, start + Duration::from_secs(10)),
+            run_ramp(&mut derivative_only(), start)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "min <= max")]
+    fn test_inverted_limits_rejected() {
+        let _ = fan_pid(30.0).with_limits(1.0, 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "min <= max")]
+    fn test_nan_limit_rejected() {
+        let _ = fan_pid(30.0).with_limits(f32::NAN, 1.0);
+    }
+}
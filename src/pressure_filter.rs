@@ -0,0 +1,213 @@
+// Software oversampling and smoothing for any `PressureSensor`.
+//
+// let sensor = Filtered::new(detect().unwrap(), Filter::default());
+// let barometer = Continuous::spawn(sensor, Duration::from_secs(1));
+// barometer.latest();
+
+use crate::barometer::{Chip, Measurement, PressureSensor};
+use crate::filter::Iir;
+use crate::worker::Worker;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Scales a median absolute deviation to a standard deviation for normal noise.
+const MAD_SCALE: f32 = 1.4826;
+
+// Smallest deviation assumed, in Pa. Integer readings that mostly agree have a MAD of zero,
+// which would otherwise reject every sample that differs from the median at all.
+const MIN_DEVIATION: f32 = 1.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Filter {
+    /// Measurements averaged per reading.
+    pub samples: usize,
+    /// Drop samples further than this many deviations from the median.
+    pub max_deviation: f32,
+    /// Weight of each new reading in the exponential filter. 1.0 turns it off.
+    pub alpha: f32,
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter {
+            samples: 8,
+            max_deviation: 3.0,
+            alpha: 0.2,
+        }
+    }
+}
+
+fn median(sorted: &[f32]) -> f32 {
+    let mid = sorted.len() / 2;
+    match sorted.len() % 2 {
+        0 => (sorted[mid - 1] + sorted[mid]) / 2.0,
+        _ => sorted[mid],
+    }
+}
+
+/// Indices of the values within `max_deviation` scaled MADs of the median, the MAD taken as
+/// at least `MIN_DEVIATION`.
+pub fn inliers(values: &[f32], max_deviation: f32) -> Vec<usize> {
+    if values.is_empty() {
+        return Vec::new();
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let center = median(&sorted);
+    let mut deviations: Vec<f32> = values.iter().map(|v| (v - center).abs()).collect();
+    deviations.sort_by(f32::total_cmp);
+    let limit = max_deviation * (MAD_SCALE * median(&deviations)).max(MIN_DEVIATION);
+    (0..values.len())
+        .filter(|&i| (values[i] - center).abs() <= limit)
+        .collect()
+}
+
+/// Mean of the values left after outlier rejection.
+pub fn robust_mean(values: &[f32], max_deviation: f32) -> f32 {
+    let kept = inliers(values, max_deviation);
+    kept.iter().map(|&i| values[i]).sum::<f32>() / kept.len() as f32
+}
+
+/// Wraps a sensor, averaging several measurements per reading and smoothing pressure.
+pub struct Filtered<S: PressureSensor> {
+    sensor: S,
+    filter: Filter,
+    iir: Iir,
+}
+
+impl<S: PressureSensor> Filtered<S> {
+    pub fn new(sensor: S, filter: Filter) -> Filtered<S> {
+        Filtered {
+            sensor,
+            filter,
+            iir: Iir::new(filter.alpha),
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.sensor
+    }
+}
+
+impl<S: PressureSensor> PressureSensor for Filtered<S> {
+    fn chip(&self) -> Chip {
+        self.sensor.chip()
+    }
+
+    fn measure(&mut self) -> Measurement {
+        let samples: Vec<Measurement> = (0..self.filter.samples.max(1))
+            .map(|_| self.sensor.measure())
+            .collect();
+        let pressures: Vec<f32> = samples.iter().map(|m| m.pressure).collect();
+        let kept = inliers(&pressures, self.filter.max_deviation);
+        let count = kept.len() as f32;
+        let pressure = kept.iter().map(|&i| pressures[i]).sum::<f32>() / count;
+        let celsius = kept.iter().map(|&i| samples[i].celsius).sum::<f32>() / count;
+        let humidity = samples[0].humidity.map(|_| {
+            kept.iter()
+                .filter_map(|&i| samples[i].humidity)
+                .sum::<f32>()
+                / count
+        });
+        Measurement {
+            celsius,
+            pressure: self.iir.update(pressure),
+            humidity,
+        }
+    }
+}
+
+/// Keeps measuring on a background thread so the latest value is always at hand.
+pub struct Continuous {
+    latest: Arc<Mutex<Measurement>>,
+    worker: Worker,
+}
+
+impl Continuous {
+    /// Take one measurement up front, then one every `interval`.
+    pub fn spawn<S: PressureSensor + Send + 'static>(
+        mut sensor: S,
+        interval: Duration,
+    ) -> Continuous {
+        let latest = Arc::new(Mutex::new(sensor.measure()));
+        let thread_latest = Arc::clone(&latest);
+        let worker = Worker::spawn("barometer", move |running| {
+            while running.load(Ordering::Relaxed) {
+                thread::sleep(interval);
+                let measurement = sensor.measure();
+                *thread_latest.lock().expect("Lock should not be poisoned") = measurement;
+            }
+        });
+        Continuous { latest, worker }
+    }
+
+    pub fn latest(&self) -> Measurement {
+        *self.latest.lock().expect("Lock should not be poisoned")
+    }
+
+    pub fn stop(&mut self) {
+        self.worker.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Replay {
+        pressures: Vec<f32>,
+        index: usize,
+    }
+
+    impl PressureSensor for Replay {
+        fn chip(&self) -> Chip {
+            Chip::Bmp085
+        }
+
+        fn measure(&mut self) -> Measurement {
+            let pressure = self.pressures[self.index % self.pressures.len()];
+            self.index += 1;
+            Measurement {
+                celsius: 20.0,
+                pressure,
+                humidity: None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_robust_mean_drops_spike() {
+        let values = [
+            100_000.0, 100_002.0, 99_998.0, 100_001.0, 100_650.0, 99_999.0,
+        ];
+        assert_eq!(inliers(&values, 3.0), vec![0, 1, 2, 3, 5]);
+        assert_eq!(robust_mean(&values, 3.0), 100_000.0);
+    }
+
+    #[test]
+    fn test_inliers_with_zero_mad() {
+        let values = [
+            100_000.0, 100_000.0, 100_000.0, 100_000.0, 100_000.0, 100_001.0, 99_999.0, 100_050.0,
+        ];
+        assert_eq!(inliers(&values, 3.0), vec![0, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_filtered_measure() {
+        let replay = Replay {
+            pressures: vec![100_000.0, 100_004.0, 90_000.0, 99_996.0],
+            index: 0,
+        };
+        let filter = Filter {
+            samples: 4,
+            max_deviation: 3.0,
+            alpha: 1.0,
+        };
+        let mut filtered = Filtered::new(replay, filter);
+        let measurement = filtered.measure();
+        assert_eq!(measurement.pressure, 100_000.0);
+        assert_eq!(measurement.celsius, 20.0);
+    }
+}
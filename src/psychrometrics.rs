@@ -0,0 +1,198 @@
+// Derived humidity and comfort metrics from temperature, relative humidity and pressure.
+//
+// Magnus coefficients from Sonntag 1990:
+// https://www.vaisala.com/sites/default/files/documents/Humidity_Conversion_Formulas_B210973EN-F.pdf
+// Heat index from the NWS Rothfusz regression:
+// https://www.wpc.ncep.noaa.gov/html/heatindex_equation.shtml
+
+use crate::config::TemperatureUnit;
+
+// Magnus coefficients over water and over ice.
+const WATER_A: f32 = 17.62;
+const WATER_B: f32 = 243.12;
+const ICE_A: f32 = 22.46;
+const ICE_B: f32 = 272.62;
+const BASE_HPA: f32 = 6.112;
+
+const KELVIN: f32 = 273.15;
+
+/// Saturation vapor pressure over water in hPa.
+pub fn saturation_vapor_pressure(celsius: f32) -> f32 {
+    BASE_HPA * f32::exp(WATER_A * celsius / (WATER_B + celsius))
+}
+
+/// Actual vapor pressure in hPa.
+pub fn vapor_pressure(celsius: f32, humidity: f32) -> f32 {
+    saturation_vapor_pressure(celsius) * humidity / 100.0
+}
+
+/// Temperature at which air saturates over water, Magnus formula.
+pub fn dew_point(celsius: f32, humidity: f32) -> f32 {
+    let gamma = f32::ln(humidity / 100.0) + WATER_A * celsius / (WATER_B + celsius);
+    WATER_B * gamma / (WATER_A - gamma)
+}
+
+/// Temperature at which air saturates over ice.
+pub fn frost_point(celsius: f32, humidity: f32) -> f32 {
+    let gamma = f32::ln(vapor_pressure(celsius, humidity) / BASE_HPA);
+    ICE_B * gamma / (ICE_A - gamma)
+}
+
+/// Apparent temperature in hot, humid air.
+///
+/// Below ~27 C the regression doesn't hold and Steadman's simple formula is used.
+pub fn heat_index(celsius: f32, humidity: f32) -> f32 {
+    let t = celsius * 9.0 / 5.0 + 32.0;
+    let rh = humidity;
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    let mut hi = if (simple + t) / 2.0 < 80.0 {
+        simple
+    } else {
+        -42.379 + 2.049_015_2 * t + 10.143_332 * rh
+            - 0.224_755_4 * t * rh
+            - 0.006_837_83 * t * t
+            - 0.054_817_17 * rh * rh
+            + 0.001_228_74 * t * t * rh
+            + 0.000_852_82 * t * rh * rh
+            - 0.000_001_99 * t * t * rh * rh
+    };
+    if rh < 13.0 && (80.0..=112.0).contains(&t) {
+        hi -= (13.0 - rh) / 4.0 * f32::sqrt((17.0 - (t - 95.0).abs()) / 17.0);
+    } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+        hi += (rh - 85.0) / 10.0 * (87.0 - t) / 5.0;
+    }
+    (hi - 32.0) * 5.0 / 9.0
+}
+
+/// Canadian humidex from temperature and dew point.
+pub fn humidex(celsius: f32, dew_point: f32) -> f32 {
+    let e = 6.11 * f32::exp(5417.753 * (1.0 / 273.16 - 1.0 / (KELVIN + dew_point)));
+    celsius + 0.5555 * (e - 10.0)
+}
+
+/// Temperature of a ventilated wet thermometer.
+///
+/// Solve the psychrometer equation by bisection; pressure in Pa.
+pub fn wet_bulb(celsius: f32, humidity: f32, pressure: f32) -> f32 {
+    let e = vapor_pressure(celsius, humidity);
+    let hpa = pressure / 100.0;
+    let mut low = dew_point(celsius, humidity).min(celsius);
+    let mut high = celsius;
+    for _ in 0..40 {
+        let tw = (low + high) / 2.0;
+        let gamma = 6.6e-4 * (1.0 + 0.001_15 * tw);
+        let estimate = saturation_vapor_pressure(tw) - gamma * hpa * (celsius - tw);
+        if estimate > e {
+            high = tw;
+        } else {
+            low = tw;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Grams of water vapor per cubic metre of air.
+pub fn absolute_humidity(celsius: f32, humidity: f32) -> f32 {
+    216.7 * vapor_pressure(celsius, humidity) / (KELVIN + celsius)
+}
+
+/// Grams of water vapor per kilogram of dry air; pressure in Pa.
+pub fn mixing_ratio(celsius: f32, humidity: f32, pressure: f32) -> f32 {
+    let e = vapor_pressure(celsius, humidity);
+    621.97 * e / (pressure / 100.0 - e)
+}
+
+/// Every metric at once, for display.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Comfort {
+    pub dew_point: f32,
+    pub frost_point: f32,
+    pub heat_index: f32,
+    pub humidex: f32,
+    pub wet_bulb: f32,
+    pub absolute_humidity: f32,
+    pub mixing_ratio: f32,
+}
+
+impl Comfort {
+    /// Celsius, percent relative humidity and Pa.
+    pub fn new(celsius: f32, humidity: f32, pressure: f32) -> Comfort {
+        let dew_point = dew_point(celsius, humidity);
+        Self {
+            dew_point,
+            frost_point: frost_point(celsius, humidity),
+            heat_index: heat_index(celsius, humidity),
+            humidex: humidex(celsius, dew_point),
+            wet_bulb: wet_bulb(celsius, humidity, pressure),
+            absolute_humidity: absolute_humidity(celsius, humidity),
+            mixing_ratio: mixing_ratio(celsius, humidity, pressure),
+        }
+    }
+
+    /// Two line pages sized for the 16x2 LCD.
+    pub fn lcd_pages(&self, unit: TemperatureUnit) -> Vec<Vec<String>> {
+        Vec::from([
+            Vec::from([
+                format!("Dew pt {}", unit.format(self.dew_point)),
+                format!("Frost  {}", unit.format(self.frost_point)),
+            ]),
+            Vec::from([
+                format!("Heat idx {}", unit.format(self.heat_index)),
+                format!("Humidex  {:.1}", self.humidex),
+            ]),
+            Vec::from([
+                format!("Wet bulb {}", unit.format(self.wet_bulb)),
+                format!("Abs {:.1} g/m3", self.absolute_humidity),
+            ]),
+            Vec::from([
+                "Mix ratio".to_string(),
+                format!("{:.1} g/kg", self.mixing_ratio),
+            ]),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::assert_near;
+
+    #[test]
+    fn test_dew_and_frost_point() {
+        assert_near(dew_point(25.0, 60.0), 16.7, 0.1);
+        assert_near(dew_point(20.0, 100.0), 20.0, 0.01);
+        assert_near(frost_point(-10.0, 80.0), -11.4, 0.1);
+    }
+
+    #[test]
+    fn test_heat_index() {
+        // NWS table: 90 F at 70 % feels like 106 F.
+        assert_near(heat_index(32.22, 70.0), 41.1, 0.3);
+        // Mild air is left alone.
+        assert_near(heat_index(20.0, 50.0), 19.5, 0.5);
+    }
+
+    #[test]
+    fn test_humidex() {
+        // Environment Canada table: 30 C with a 15 C dew point reads 34.
+        assert_near(humidex(30.0, 15.0), 34.0, 0.5);
+    }
+
+    #[test]
+    fn test_moisture_content() {
+        assert_near(absolute_humidity(20.0, 50.0), 8.6, 0.1);
+        assert_near(mixing_ratio(20.0, 50.0, 101_325.0), 7.3, 0.1);
+        assert_near(wet_bulb(20.0, 50.0, 101_325.0), 13.7, 0.2);
+    }
+
+    #[test]
+    fn test_lcd_pages_fit() {
+        let comfort = Comfort::new(45.0, 90.0, 100_500.0);
+        for page in comfort.lcd_pages(TemperatureUnit::Fahrenheit) {
+            assert_eq!(page.len(), 2);
+            for line in page {
+                assert!(line.len() <= 16, "{line} is too long");
+            }
+        }
+    }
+}
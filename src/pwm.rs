@@ -0,0 +1,331 @@
+// Pulse width modulation, in software on any GPIO pin or from the SoC's PWM controller.
+//
+// let mut pwm = SoftPwm::spawn(gpio::sysfs::SysFsGpioOutput::open(5).unwrap(), 100.0)?;
+// pwm.set_duty(Duty::from_speed(128));
+//
+// Hardware PWM needs the overlay, e.g. dtoverlay=pwm,pin=18,func=2 in /boot/config.txt.
+// https://www.kernel.org/doc/html/latest/driver-api/pwm.html
+
+use crate::worker::Worker;
+use gpio::GpioOut;
+use std::fs;
+use std::io;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Fraction of each period the output is high, 0.0 to 1.0.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Duty(f32);
+
+impl Duty {
+    pub const OFF: Duty = Duty(0.0);
+    pub const FULL: Duty = Duty(1.0);
+
+    pub fn new(fraction: f32) -> Duty {
+        Duty(fraction.clamp(0.0, 1.0))
+    }
+
+    /// 0 through 255, as taken by `Motor::run`.
+    pub fn from_speed(speed: u8) -> Duty {
+        Duty(speed as f32 / u8::MAX as f32)
+    }
+
+    pub fn from_percent(percent: f32) -> Duty {
+        Duty::new(percent / 100.0)
+    }
+
+    pub fn fraction(&self) -> f32 {
+        self.0
+    }
+
+    /// The rest of the period, for an output driven the other way round.
+    pub fn inverted(&self) -> Duty {
+        Duty(1.0 - self.0)
+    }
+}
+
+/// Frequencies must be positive and finite.
+pub fn check_frequency(frequency: f32) -> io::Result<()> {
+    // A tiny frequency has a period too long for a Duration.
+    if frequency.is_finite()
+        && frequency > 0.0
+        && Duration::try_from_secs_f32(1.0 / frequency).is_ok()
+    {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("PWM frequency {frequency} Hz is out of range"),
+    ))
+}
+
+/// High and low times of one period. `frequency` must pass `check_frequency`.
+pub fn split_period(frequency: f32, duty: Duty) -> (Duration, Duration) {
+    let period = Duration::from_secs_f32(1.0 / frequency);
+    let high = period.mul_f32(duty.fraction());
+    (high, period - high)
+}
+
+/// A digital output the software PWM can drive.
+pub trait Pin {
+    fn set(&mut self, high: bool);
+}
+
+impl Pin for gpio::sysfs::SysFsGpioOutput {
+    fn set(&mut self, high: bool) {
+        self.set_value(high).expect("Pin should set");
+    }
+}
+
+/// What both PWM backends can do.
+pub trait Pwm {
+    fn set_duty(&mut self, duty: Duty);
+
+    fn duty(&self) -> Duty;
+
+    /// Hz. Zero, negative or non-finite frequencies are rejected.
+    fn set_frequency(&mut self, frequency: f32) -> io::Result<()>;
+}
+
+impl<P: Pwm + ?Sized> Pwm for Box<P> {
+    fn set_duty(&mut self, duty: Duty) {
+        (**self).set_duty(duty)
+    }
+
+    fn duty(&self) -> Duty {
+        (**self).duty()
+    }
+
+    fn set_frequency(&mut self, frequency: f32) -> io::Result<()> {
+        (**self).set_frequency(frequency)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Wave {
+    frequency: f32,
+    duty: Duty,
+}
+
+impl Wave {
+    /// Levels to drive over one period and how long to hold each. A duty of 0 or 1 gives a
+    /// single level, skipping the edges at either end rather than glitching the output.
+    fn levels(&self) -> Vec<(bool, Duration)> {
+        let (high, low) = split_period(self.frequency, self.duty);
+        if high.is_zero() {
+            Vec::from([(false, low)])
+        } else if low.is_zero() {
+            Vec::from([(true, high)])
+        } else {
+            Vec::from([(true, high), (false, low)])
+        }
+    }
+
+    /// One period on `pin`, with `sleep` holding each level.
+    fn drive<P: Pin>(&self, pin: &mut P, mut sleep: impl FnMut(Duration)) {
+        for (level, time) in self.levels() {
+            pin.set(level);
+            sleep(time);
+        }
+    }
+}
+
+/// Toggles a pin from a background thread. Good to a few hundred Hz; sleeps jitter by
+/// tens of microseconds. Motors and LEDs don't notice, but a servo holding position will
+/// twitch, so prefer `SysfsPwm` for servos where a hardware channel is free.
+pub struct SoftPwm {
+    wave: Arc<Mutex<Wave>>,
+    worker: Worker,
+}
+
+impl SoftPwm {
+    /// Start with the output low.
+    pub fn spawn<P: Pin + Send + 'static>(mut pin: P, frequency: f32) -> io::Result<SoftPwm> {
+        check_frequency(frequency)?;
+        let wave = Arc::new(Mutex::new(Wave {
+            frequency,
+            duty: Duty::OFF,
+        }));
+        let thread_wave = Arc::clone(&wave);
+        let worker = Worker::spawn("soft pwm", move |running| {
+            while running.load(Ordering::Relaxed) {
+                let wave = *thread_wave.lock().expect("Lock should not be poisoned");
+                wave.drive(&mut pin, thread::sleep);
+            }
+            pin.set(false);
+        });
+        Ok(SoftPwm { wave, worker })
+    }
+
+    /// Stop the background thread, leaving the pin low.
+    pub fn stop(&mut self) {
+        self.worker.stop();
+    }
+}
+
+impl Pwm for SoftPwm {
+    fn set_duty(&mut self, duty: Duty) {
+        self.wave.lock().expect("Lock should not be poisoned").duty = duty;
+    }
+
+    fn duty(&self) -> Duty {
+        self.wave.lock().expect("Lock should not be poisoned").duty
+    }
+
+    fn set_frequency(&mut self, frequency: f32) -> io::Result<()> {
+        check_frequency(frequency)?;
+        self.wave
+            .lock()
+            .expect("Lock should not be poisoned")
+            .frequency = frequency;
+        Ok(())
+    }
+}
+
+/// A channel of the kernel PWM interface under /sys/class/pwm.
+pub struct SysfsPwm {
+    path: String,
+    period: u64,
+    duty: Duty,
+}
+
+impl SysfsPwm {
+    /// Export and enable `channel` of pwmchip`chip` at `frequency`, output low.
+    pub fn new(chip: u32, channel: u32, frequency: f32) -> io::Result<SysfsPwm> {
+        check_frequency(frequency)?;
+        let chip_path = format!("/sys/class/pwm/pwmchip{chip}");
+        let path = format!("{chip_path}/pwm{channel}");
+        if fs::metadata(&path).is_err() {
+            fs::write(format!("{chip_path}/export"), channel.to_string())?;
+            // udev needs a moment to make the new files writable.
+            thread::sleep(Duration::from_millis(100));
+        }
+        let mut pwm = SysfsPwm {
+            path,
+            period: 0,
+            duty: Duty::OFF,
+        };
+        pwm.write("duty_cycle", 0)?;
+        pwm.write_period(frequency)?;
+        pwm.write("enable", 1)?;
+        Ok(pwm)
+    }
+
+    fn write(&self, file: &str, value: u64) -> io::Result<()> {
+        fs::write(format!("{}/{file}", self.path), value.to_string())
+    }
+
+    fn write_period(&mut self, frequency: f32) -> io::Result<()> {
+        let period = (1e9 / frequency as f64) as u64;
+        // The duty cycle may never exceed the period, so shrink it first.
+        self.write("duty_cycle", 0)?;
+        self.write("period", period)?;
+        self.period = period;
+        self.write("duty_cycle", self.duty_ns(self.duty))
+    }
+
+    fn duty_ns(&self, duty: Duty) -> u64 {
+        (self.period as f64 * duty.fraction() as f64) as u64
+    }
+}
+
+impl Pwm for SysfsPwm {
+    fn set_duty(&mut self, duty: Duty) {
+        self.write("duty_cycle", self.duty_ns(duty))
+            .expect("Duty cycle should write");
+        self.duty = duty;
+    }
+
+    fn duty(&self) -> Duty {
+        self.duty
+    }
+
+    fn set_frequency(&mut self, frequency: f32) -> io::Result<()> {
+        check_frequency(frequency)?;
+        self.write_period(frequency)
+    }
+}
+
+impl Drop for SysfsPwm {
+    fn drop(&mut self) {
+        let _ = self.write("enable", 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{assert_near, SimulatedPin};
+
+    #[test]
+    fn test_duty_conversions() {
+        assert_eq!(Duty::from_speed(255), Duty::FULL);
+        assert_eq!(Duty::from_percent(150.0), Duty::FULL);
+        assert_eq!(Duty::from_percent(25.0).inverted(), Duty::new(0.75));
+        assert_eq!(
+            split_period(100.0, Duty::new(0.25)),
+            (Duration::from_micros(2500), Duration::from_micros(7500))
+        );
+    }
+
+    #[test]
+    fn test_bad_frequencies_rejected() {
+        for frequency in [0.0, -50.0, 1e-30, f32::NAN, f32::INFINITY] {
+            assert!(check_frequency(frequency).is_err());
+            assert!(SoftPwm::spawn(SimulatedPin::default(), frequency).is_err());
+        }
+        let mut pwm = SoftPwm::spawn(SimulatedPin::default(), 50.0).unwrap();
+        assert!(pwm.set_frequency(0.0).is_err());
+        assert!(pwm.set_frequency(100.0).is_ok());
+    }
+
+    #[test]
+    fn test_wave_levels() {
+        let wave = |duty| Wave {
+            frequency: 50.0,
+            duty,
+        };
+        assert_eq!(
+            wave(Duty::new(0.3)).levels(),
+            vec![
+                (true, Duration::from_millis(6)),
+                (false, Duration::from_millis(14))
+            ]
+        );
+        assert_eq!(
+            wave(Duty::FULL).levels(),
+            vec![(true, Duration::from_millis(20))]
+        );
+        assert_eq!(
+            wave(Duty::OFF).levels(),
+            vec![(false, Duration::from_millis(20))]
+        );
+    }
+
+    #[test]
+    fn test_duty_on_pin() {
+        for duty in [0.0, 0.1, 0.5, 0.75, 1.0] {
+            let wave = Wave {
+                frequency: 200.0,
+                duty: Duty::new(duty),
+            };
+            let mut pin = SimulatedPin::default();
+            let clock = pin.clone();
+            for _ in 0..10 {
+                wave.drive(&mut pin, |time| clock.sleep(time));
+            }
+            assert_near(pin.duty(), duty, 0.001);
+        }
+    }
+
+    #[test]
+    fn test_soft_pwm_stops_low() {
+        let pin = SimulatedPin::default();
+        let mut pwm = SoftPwm::spawn(pin.clone(), 1000.0).unwrap();
+        pwm.set_duty(Duty::FULL);
+        pwm.stop();
+        assert!(!pin.level());
+    }
+}
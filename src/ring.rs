@@ -0,0 +1,58 @@
+// Fixed size history buffer shared by the sampling threads.
+
+use std::collections::VecDeque;
+
+/// Keeps the newest `capacity` items, dropping the oldest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ring<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<T> Ring<T> {
+    pub fn new(capacity: usize) -> Ring<T> {
+        let capacity = capacity.max(1);
+        Ring {
+            items: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, item: T) {
+        if self.items.len() == self.capacity {
+            self.items.pop_front();
+        }
+        self.items.push_back(item);
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Up to `count` of the newest items, oldest first.
+    pub fn last(&self, count: usize) -> impl Iterator<Item = &T> {
+        self.items
+            .iter()
+            .skip(self.items.len().saturating_sub(count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_keeps_newest() {
+        let mut ring = Ring::new(3);
+        for i in 0..5 {
+            ring.push(i);
+        }
+        assert_eq!(ring.len(), 3);
+        assert_eq!(ring.last(10).copied().collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert_eq!(ring.last(2).copied().collect::<Vec<_>>(), vec![3, 4]);
+    }
+}
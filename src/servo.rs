@@ -0,0 +1,342 @@
+// Hobby servos such as the SG90: a 50 Hz frame with a 0.5 to 2.5 ms pulse setting the angle.
+//
+// let mut pan = Servo::new(12);
+// pan.sweep_to(90.0, 60.0)?; // at 60 degrees a second
+//
+// let mut head = Servos::new(Vec::from([Servo::new(12), Servo::new(13)]));
+// head.sweep_to(&[45.0, 120.0], 90.0)?; // both arrive together
+//
+// Software pulses jitter a little; for a steady hold use /sys/class/pwm on GPIO 12/13.
+
+use crate::pwm::{Duty, Pwm, SoftPwm, SysfsPwm};
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+pub const FREQUENCY: f32 = 50.0;
+
+// One frame, the finest step a sweep can make.
+const FRAME: Duration = Duration::from_millis(20);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ServoError {
+    /// The pulses don't widen from `min` to `max`, or both ends have the same angle.
+    BadRange,
+    /// Sweep rate zero, negative or NaN.
+    BadRate(f32),
+    /// A target angle that is infinite or NaN.
+    BadAngle(f32),
+}
+
+impl fmt::Display for ServoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServoError::BadRange => write!(f, "pulse range needs min < max and distinct angles"),
+            ServoError::BadRate(rate) => write!(f, "sweep rate {rate} deg/s is not positive"),
+            ServoError::BadAngle(angle) => write!(f, "angle {angle} is not a number of degrees"),
+        }
+    }
+}
+
+impl std::error::Error for ServoError {}
+
+/// Pulse widths at the two ends of travel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PulseRange {
+    min: Duration,
+    max: Duration,
+    min_angle: f32,
+    max_angle: f32,
+}
+
+impl Default for PulseRange {
+    // SG90. Many servos are only rated for 1 to 2 ms; narrow this if one buzzes at the ends.
+    fn default() -> PulseRange {
+        PulseRange {
+            min: Duration::from_micros(500),
+            max: Duration::from_micros(2500),
+            min_angle: 0.0,
+            max_angle: 180.0,
+        }
+    }
+}
+
+impl PulseRange {
+    /// `min_angle` degrees at the `min` pulse and `max_angle` at `max`. The angles may run
+    /// either way round, for a servo mounted upside down.
+    pub fn new(
+        min: Duration,
+        max: Duration,
+        min_angle: f32,
+        max_angle: f32,
+    ) -> Result<PulseRange, ServoError> {
+        let angles_ok = min_angle.is_finite() && max_angle.is_finite() && min_angle != max_angle;
+        if min >= max || !angles_ok {
+            return Err(ServoError::BadRange);
+        }
+        Ok(PulseRange {
+            min,
+            max,
+            min_angle,
+            max_angle,
+        })
+    }
+
+    /// The nearest angle the servo can reach.
+    pub fn clamp(&self, angle: f32) -> f32 {
+        let (low, high) = match self.min_angle <= self.max_angle {
+            true => (self.min_angle, self.max_angle),
+            false => (self.max_angle, self.min_angle),
+        };
+        angle.clamp(low, high)
+    }
+
+    /// Where a move to `angle` ends up; infinite or NaN angles are rejected.
+    pub fn target(&self, angle: f32) -> Result<f32, ServoError> {
+        match angle.is_finite() {
+            true => Ok(self.clamp(angle)),
+            false => Err(ServoError::BadAngle(angle)),
+        }
+    }
+
+    /// Angles beyond either end are clamped.
+    pub fn pulse(&self, angle: f32) -> Duration {
+        let fraction = (self.clamp(angle) - self.min_angle) / (self.max_angle - self.min_angle);
+        self.min + (self.max - self.min).mul_f32(fraction)
+    }
+
+    pub fn duty(&self, angle: f32) -> Duty {
+        Duty::new(self.pulse(angle).as_secs_f32() * FREQUENCY)
+    }
+}
+
+/// Angle for each frame of a sweep from `from` to `to`, ending exactly on `to`.
+pub fn sweep(from: f32, to: f32, degrees_per_second: f32) -> Result<Vec<f32>, ServoError> {
+    if degrees_per_second.is_nan() || degrees_per_second <= 0.0 {
+        return Err(ServoError::BadRate(degrees_per_second));
+    }
+    if let Some(&angle) = [from, to].iter().find(|angle| !angle.is_finite()) {
+        return Err(ServoError::BadAngle(angle));
+    }
+    let per_frame = degrees_per_second * FRAME.as_secs_f32();
+    let frames = ((to - from).abs() / per_frame).ceil().max(1.0) as usize;
+    Ok((1..=frames)
+        .map(|i| from + (to - from) * i as f32 / frames as f32)
+        .collect())
+}
+
+pub struct Servo {
+    pwm: Box<dyn Pwm + Send>,
+    range: PulseRange,
+    angle: Option<f32>,
+}
+
+impl Servo {
+    /// Software pulses on any GPIO pin.
+    pub fn new(pin: u16) -> Servo {
+        let out = gpio::sysfs::SysFsGpioOutput::open(pin).expect("Pin should be active");
+        let pwm = SoftPwm::spawn(out, FREQUENCY).expect("Frequency should be valid");
+        Servo::with_pwm(Box::new(pwm))
+    }
+
+    /// Hardware pulses from /sys/class/pwm.
+    pub fn with_sysfs_pwm(chip: u32, channel: u32) -> Servo {
+        let pwm = SysfsPwm::new(chip, channel, FREQUENCY).expect("PWM channel should export");
+        Servo::with_pwm(Box::new(pwm))
+    }
+
+    /// Starts detached, without pulses, until the first angle is set.
+    pub fn with_pwm(mut pwm: Box<dyn Pwm + Send>) -> Servo {
+        pwm.set_frequency(FREQUENCY).expect("Frequency should set");
+        pwm.set_duty(Duty::OFF);
+        Servo {
+            pwm,
+            range: PulseRange::default(),
+            angle: None,
+        }
+    }
+
+    pub fn set_range(&mut self, range: PulseRange) {
+        self.range = range;
+    }
+
+    /// Last angle set, None while detached.
+    pub fn angle(&self) -> Option<f32> {
+        self.angle
+    }
+
+    /// Move as fast as the servo can, clamped to the range. NaN is ignored.
+    pub fn set_angle(&mut self, angle: f32) {
+        if angle.is_nan() {
+            return;
+        }
+        let angle = self.range.clamp(angle);
+        self.pwm.set_duty(self.range.duty(angle));
+        self.angle = Some(angle);
+    }
+
+    /// Move at `degrees_per_second`, blocking until there. Jumps when detached, since the
+    /// starting position is unknown. Angles beyond the range stop at its end.
+    pub fn sweep_to(&mut self, angle: f32, degrees_per_second: f32) -> Result<(), ServoError> {
+        let angle = self.range.target(angle)?;
+        let Some(from) = self.angle else {
+            self.set_angle(angle);
+            return Ok(());
+        };
+        for step in sweep(from, angle, degrees_per_second)? {
+            self.set_angle(step);
+            thread::sleep(FRAME);
+        }
+        Ok(())
+    }
+
+    /// Stop the pulses. The servo goes limp and stops drawing holding current.
+    pub fn detach(&mut self) {
+        self.pwm.set_duty(Duty::OFF);
+        self.angle = None;
+    }
+}
+
+/// Several servos moved together, e.g. pan and tilt.
+pub struct Servos {
+    servos: Vec<Servo>,
+}
+
+impl Servos {
+    pub fn new(servos: Vec<Servo>) -> Servos {
+        Servos { servos }
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> &mut Servo {
+        &mut self.servos[index]
+    }
+
+    /// Move every servo to its angle in `angles` so they all arrive at the same time,
+    /// the one with furthest to go moving at `degrees_per_second`. Angles beyond a servo's
+    /// range stop at its end.
+    pub fn sweep_to(&mut self, angles: &[f32], degrees_per_second: f32) -> Result<(), ServoError> {
+        let angles = self
+            .servos
+            .iter()
+            .zip(angles)
+            .map(|(servo, &angle)| servo.range.target(angle))
+            .collect::<Result<Vec<f32>, ServoError>>()?;
+        let distance = self
+            .servos
+            .iter()
+            .zip(&angles)
+            .map(|(servo, &to)| servo.angle.map_or(0.0, |from| (to - from).abs()))
+            .fold(0.0, f32::max);
+        let frames = sweep(0.0, distance, degrees_per_second)?.len();
+        let paths: Vec<Vec<f32>> = self
+            .servos
+            .iter()
+            .zip(&angles)
+            .map(|(servo, &to)| {
+                let from = servo.angle.unwrap_or(to);
+                (1..=frames)
+                    .map(|i| from + (to - from) * i as f32 / frames as f32)
+                    .collect()
+            })
+            .collect();
+        for frame in 0..frames {
+            for (servo, path) in self.servos.iter_mut().zip(&paths) {
+                servo.set_angle(path[frame]);
+            }
+            thread::sleep(FRAME);
+        }
+        Ok(())
+    }
+
+    pub fn detach(&mut self) {
+        for servo in self.servos.iter_mut() {
+            servo.detach();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pulse_range() {
+        let range = PulseRange::default();
+        assert_eq!(range.pulse(0.0), Duration::from_micros(500));
+        assert_eq!(range.pulse(90.0), Duration::from_micros(1500));
+        assert_eq!(range.pulse(270.0), Duration::from_micros(2500));
+        assert!((range.duty(90.0).fraction() - 0.075).abs() < 1e-6);
+        // Reversed mapping for a servo mounted upside down.
+        let reversed =
+            PulseRange::new(range.min, range.max, 90.0, -90.0).expect("Range should be valid");
+        assert_eq!(reversed.pulse(90.0), Duration::from_micros(500));
+        assert_eq!(reversed.pulse(-90.0), Duration::from_micros(2500));
+    }
+
+    #[test]
+    fn test_pulse_range_rejects_degenerate() {
+        let (min, max) = (Duration::from_micros(1000), Duration::from_micros(2000));
+        assert_eq!(
+            PulseRange::new(min, max, 45.0, 45.0),
+            Err(ServoError::BadRange)
+        );
+        assert_eq!(
+            PulseRange::new(max, min, 0.0, 180.0),
+            Err(ServoError::BadRange)
+        );
+        assert!(PulseRange::new(min, max, 0.0, 180.0).is_ok());
+    }
+
+    #[test]
+    fn test_sweep() {
+        // 100 degrees a second is 2 degrees a frame.
+        let steps = sweep(10.0, 0.0, 100.0).unwrap();
+        assert_eq!(steps.len(), 5);
+        assert_eq!(steps[0], 8.0);
+        assert_eq!(*steps.last().unwrap(), 0.0);
+        assert_eq!(sweep(30.0, 30.0, 100.0), Ok(vec![30.0]));
+        assert_eq!(sweep(0.0, 90.0, 0.0), Err(ServoError::BadRate(0.0)));
+        assert_eq!(sweep(0.0, 90.0, -10.0), Err(ServoError::BadRate(-10.0)));
+        assert!(matches!(
+            sweep(0.0, f32::NAN, 100.0),
+            Err(ServoError::BadAngle(_))
+        ));
+    }
+
+    struct NullPwm(Duty);
+
+    impl Pwm for NullPwm {
+        fn set_duty(&mut self, duty: Duty) {
+            self.0 = duty;
+        }
+
+        fn duty(&self) -> Duty {
+            self.0
+        }
+
+        fn set_frequency(&mut self, _: f32) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_targets_clamped_to_range() {
+        let range = PulseRange::default();
+        assert_eq!(range.target(1e30), Ok(180.0));
+        assert_eq!(range.target(-5.0), Ok(0.0));
+        assert_eq!(
+            range.target(f32::INFINITY),
+            Err(ServoError::BadAngle(f32::INFINITY))
+        );
+        let servo = || Servo::with_pwm(Box::new(NullPwm(Duty::OFF)));
+        let mut servos = Servos::new(Vec::from([servo(), servo()]));
+        servos.get_mut(0).set_angle(170.0);
+        servos.get_mut(1).set_angle(90.0);
+        // One frame to the end stop, not ages sweeping towards 1e30.
+        servos.sweep_to(&[1e30, 92.0], 100.0).unwrap();
+        assert_eq!(servos.get_mut(0).angle(), Some(180.0));
+        assert_eq!(servos.get_mut(1).angle(), Some(92.0));
+        assert!(servos.sweep_to(&[f32::NAN, 0.0], 100.0).is_err());
+        assert_eq!(servos.get_mut(1).angle(), Some(92.0));
+    }
+}
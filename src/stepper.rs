@@ -0,0 +1,399 @@
+// Stepper motors: a 28BYJ-48 on a ULN2003 board, or any motor on an A4988 STEP/DIR driver.
+//
+// let mut stepper = Stepper::new(Uln2003::new(ULN2003_PINS, Sequence::Half));
+// stepper.set_profile(Profile { max_speed: 800.0, acceleration: 1600.0 })?;
+// stepper.move_to(4096)?; // one output shaft turn in half steps
+// stepper.release();
+//
+// A4988: https://www.pololu.com/file/0J450/A4988.pdf
+
+use crate::pwm::Pin;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+// IN1 through IN4. Free unless SPI0 is enabled; the others are taken by the kit's boards.
+pub const ULN2003_PINS: [u16; 4] = [7, 8, 9, 10];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StepperError {
+    /// Cruise speed zero, negative or not finite.
+    BadSpeed(f32),
+    /// Acceleration zero, negative or NaN.
+    BadAcceleration(f32),
+    /// More steps than one move can make.
+    TooFar(i64),
+}
+
+impl fmt::Display for StepperError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StepperError::BadSpeed(speed) => {
+                write!(f, "speed {speed} steps/s is not positive and finite")
+            }
+            StepperError::BadAcceleration(acceleration) => {
+                write!(f, "acceleration {acceleration} steps/s^2 is not positive")
+            }
+            StepperError::TooFar(steps) => {
+                write!(f, "{steps} steps is more than {} in one move", u32::MAX)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StepperError {}
+
+/// Coil patterns for a four phase unipolar motor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sequence {
+    /// One coil at a time. Least current, least torque.
+    Wave,
+    /// Two coils at a time. Most torque.
+    Full,
+    /// Alternates one and two coils, for twice the steps per turn.
+    Half,
+}
+
+impl Sequence {
+    pub fn phases(&self) -> &'static [[bool; 4]] {
+        const WAVE: [[bool; 4]; 4] = [
+            [true, false, false, false],
+            [false, true, false, false],
+            [false, false, true, false],
+            [false, false, false, true],
+        ];
+        const FULL: [[bool; 4]; 4] = [
+            [true, true, false, false],
+            [false, true, true, false],
+            [false, false, true, true],
+            [true, false, false, true],
+        ];
+        const HALF: [[bool; 4]; 8] = [
+            [true, false, false, false],
+            [true, true, false, false],
+            [false, true, false, false],
+            [false, true, true, false],
+            [false, false, true, false],
+            [false, false, true, true],
+            [false, false, false, true],
+            [true, false, false, true],
+        ];
+        match self {
+            Sequence::Wave => &WAVE,
+            Sequence::Full => &FULL,
+            Sequence::Half => &HALF,
+        }
+    }
+}
+
+/// What `Stepper` needs from a driver board.
+pub trait StepperDriver {
+    /// One step, forward or back.
+    fn step(&mut self, forward: bool);
+
+    /// Deenergize the coils; the shaft turns freely and may lose position.
+    fn release(&mut self);
+
+    /// Energize the coils at the current step.
+    fn hold(&mut self);
+}
+
+/// ULN2003 Darlington array driving the four coils directly.
+pub struct Uln2003<P: Pin = gpio::sysfs::SysFsGpioOutput> {
+    pins: [P; 4],
+    sequence: Sequence,
+    phase: usize,
+}
+
+impl Uln2003 {
+    /// IN1 through IN4.
+    pub fn new(pins: [u16; 4], sequence: Sequence) -> Uln2003 {
+        Uln2003::with_pins(
+            pins.map(|pin| gpio::sysfs::SysFsGpioOutput::open(pin).expect("Pin should be active")),
+            sequence,
+        )
+    }
+}
+
+impl<P: Pin> Uln2003<P> {
+    /// Starts released.
+    pub fn with_pins(pins: [P; 4], sequence: Sequence) -> Uln2003<P> {
+        let mut driver = Uln2003 {
+            pins,
+            sequence,
+            phase: 0,
+        };
+        driver.release();
+        driver
+    }
+
+    fn write(&mut self, coils: [bool; 4]) {
+        for (pin, coil) in self.pins.iter_mut().zip(coils) {
+            pin.set(coil);
+        }
+    }
+}
+
+impl<P: Pin> StepperDriver for Uln2003<P> {
+    fn step(&mut self, forward: bool) {
+        let count = self.sequence.phases().len();
+        self.phase = match forward {
+            true => (self.phase + 1) % count,
+            false => (self.phase + count - 1) % count,
+        };
+        self.hold();
+    }
+
+    fn release(&mut self) {
+        self.write([false; 4]);
+    }
+
+    fn hold(&mut self) {
+        self.write(self.sequence.phases()[self.phase]);
+    }
+}
+
+/// STEP/DIR driver such as the A4988 or DRV8825. Microstepping is set by its MS pins.
+pub struct A4988<P: Pin = gpio::sysfs::SysFsGpioOutput> {
+    step: P,
+    dir: P,
+    /// Active low.
+    enable: Option<P>,
+}
+
+impl A4988 {
+    pub fn new(step: u16, dir: u16, enable: Option<u16>) -> A4988 {
+        let open = |pin| gpio::sysfs::SysFsGpioOutput::open(pin).expect("Pin should be active");
+        A4988::with_pins(open(step), open(dir), enable.map(open))
+    }
+}
+
+impl<P: Pin> A4988<P> {
+    /// Starts enabled.
+    pub fn with_pins(mut step: P, dir: P, enable: Option<P>) -> A4988<P> {
+        step.set(false);
+        let mut driver = A4988 { step, dir, enable };
+        driver.hold();
+        driver
+    }
+}
+
+impl<P: Pin> StepperDriver for A4988<P> {
+    fn step(&mut self, forward: bool) {
+        self.dir.set(forward);
+        // Both the DIR setup time and the STEP pulse width are about a microsecond.
+        thread::sleep(Duration::from_micros(2));
+        self.step.set(true);
+        thread::sleep(Duration::from_micros(2));
+        self.step.set(false);
+    }
+
+    fn release(&mut self) {
+        if let Some(enable) = self.enable.as_mut() {
+            enable.set(true);
+        }
+    }
+
+    fn hold(&mut self) {
+        if let Some(enable) = self.enable.as_mut() {
+            enable.set(false);
+        }
+    }
+}
+
+/// Trapezoidal speed profile.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Profile {
+    /// Steps per second at cruise.
+    pub max_speed: f32,
+    /// Steps per second per second. Infinite starts at full speed.
+    pub acceleration: f32,
+}
+
+impl Default for Profile {
+    // Comfortable for a 28BYJ-48 in half steps.
+    fn default() -> Profile {
+        Profile {
+            max_speed: 500.0,
+            acceleration: 1000.0,
+        }
+    }
+}
+
+impl Profile {
+    pub fn check(&self) -> Result<(), StepperError> {
+        if !self.max_speed.is_finite() || self.max_speed <= 0.0 {
+            return Err(StepperError::BadSpeed(self.max_speed));
+        }
+        if self.acceleration.is_nan() || self.acceleration <= 0.0 {
+            return Err(StepperError::BadAcceleration(self.acceleration));
+        }
+        Ok(())
+    }
+
+    /// Delay after step `i` of `steps`: speeding up, cruising, then slowing down to stop
+    /// exactly on the last step. Short moves never reach full speed.
+    pub fn delay(&self, i: u32, steps: u32) -> Duration {
+        // v^2 = 2as from the start and to the end of the move.
+        let from_start = f32::sqrt(2.0 * self.acceleration * (i as f32 + 1.0));
+        let to_end = f32::sqrt(2.0 * self.acceleration * (steps - i) as f32);
+        let speed = self.max_speed.min(from_start).min(to_end);
+        Duration::from_secs_f32(1.0 / speed)
+    }
+
+    /// `delay` for every step of a move.
+    pub fn delays(&self, steps: u32) -> impl Iterator<Item = Duration> + '_ {
+        (0..steps).map(move |i| self.delay(i, steps))
+    }
+}
+
+/// Tracks position in steps from where it started, or from `set_position`.
+pub struct Stepper<D: StepperDriver> {
+    driver: D,
+    position: i64,
+    profile: Profile,
+}
+
+impl<D: StepperDriver> Stepper<D> {
+    pub fn new(driver: D) -> Stepper<D> {
+        Stepper {
+            driver,
+            position: 0,
+            profile: Profile::default(),
+        }
+    }
+
+    pub fn set_profile(&mut self, profile: Profile) -> Result<(), StepperError> {
+        profile.check()?;
+        self.profile = profile;
+        Ok(())
+    }
+
+    /// Cruise speed in steps per second.
+    pub fn set_speed(&mut self, steps_per_second: f32) -> Result<(), StepperError> {
+        self.set_profile(Profile {
+            max_speed: steps_per_second,
+            ..self.profile
+        })
+    }
+
+    pub fn position(&self) -> i64 {
+        self.position
+    }
+
+    /// Declare the current position, e.g. zero after homing.
+    pub fn set_position(&mut self, position: i64) {
+        self.position = position;
+    }
+
+    /// Move `steps` from here, negative for backwards. Blocks until done.
+    pub fn move_by(&mut self, steps: i64) -> Result<(), StepperError> {
+        let count = u32::try_from(steps.unsigned_abs()).map_err(|_| StepperError::TooFar(steps))?;
+        let forward = steps > 0;
+        for i in 0..count {
+            self.driver.step(forward);
+            self.position += if forward { 1 } else { -1 };
+            thread::sleep(self.profile.delay(i, count));
+        }
+        Ok(())
+    }
+
+    pub fn move_to(&mut self, position: i64) -> Result<(), StepperError> {
+        self.move_by(position - self.position)
+    }
+
+    pub fn release(&mut self) {
+        self.driver.release();
+    }
+
+    pub fn hold(&mut self) {
+        self.driver.hold();
+    }
+
+    pub fn driver(&mut self) -> &mut D {
+        &mut self.driver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::SimulatedPin;
+
+    #[test]
+    fn test_sequences_change_one_coil_per_half_step() {
+        let half = Sequence::Half.phases();
+        for i in 0..half.len() {
+            let next = half[(i + 1) % half.len()];
+            let changed = (0..4).filter(|&c| half[i][c] != next[c]).count();
+            assert_eq!(changed, 1);
+        }
+        assert!(Sequence::Full
+            .phases()
+            .iter()
+            .all(|phase| phase.iter().filter(|&&on| on).count() == 2));
+    }
+
+    #[test]
+    fn test_uln2003_steps_and_releases() {
+        let pins: [SimulatedPin; 4] = Default::default();
+        let levels = || pins.clone().map(|pin| pin.level());
+        let mut driver = Uln2003::with_pins(pins.clone(), Sequence::Wave);
+        assert_eq!(levels(), [false; 4]);
+        driver.step(true);
+        assert_eq!(levels(), [false, true, false, false]);
+        driver.step(false);
+        driver.step(false);
+        assert_eq!(levels(), [false, false, false, true]);
+        driver.release();
+        assert_eq!(levels(), [false; 4]);
+    }
+
+    #[test]
+    fn test_trapezoidal_profile() {
+        let profile = Profile {
+            max_speed: 1000.0,
+            acceleration: 10_000.0,
+        };
+        let delays: Vec<Duration> = profile.delays(200).collect();
+        // Symmetric, slowest at the ends, cruising at 1 ms in the middle.
+        assert_eq!(delays[0], delays[199]);
+        assert!(delays[0] > delays[10]);
+        assert_eq!(delays[100], Duration::from_millis(1));
+        // 50 steps to reach 1000 steps/s at 10000 steps/s^2.
+        assert!(delays[48] > Duration::from_millis(1));
+        assert_eq!(delays[49], Duration::from_millis(1));
+    }
+
+    #[test]
+    fn test_position_tracking() {
+        let pins: [SimulatedPin; 4] = Default::default();
+        let mut stepper = Stepper::new(Uln2003::with_pins(pins, Sequence::Half));
+        stepper
+            .set_profile(Profile {
+                max_speed: 100_000.0,
+                acceleration: f32::INFINITY,
+            })
+            .unwrap();
+        stepper.move_to(10).unwrap();
+        stepper.move_by(-25).unwrap();
+        assert_eq!(stepper.position(), -15);
+    }
+
+    #[test]
+    fn test_rejects_bad_moves_and_profiles() {
+        let pins: [SimulatedPin; 4] = Default::default();
+        let mut stepper = Stepper::new(Uln2003::with_pins(pins, Sequence::Half));
+        assert_eq!(stepper.set_speed(0.0), Err(StepperError::BadSpeed(0.0)));
+        assert!(stepper.set_speed(f32::INFINITY).is_err());
+        assert_eq!(
+            stepper.set_profile(Profile {
+                max_speed: 500.0,
+                acceleration: -1.0,
+            }),
+            Err(StepperError::BadAcceleration(-1.0))
+        );
+        assert_eq!(stepper.move_by(1 << 32), Err(StepperError::TooFar(1 << 32)));
+        assert_eq!(stepper.position(), 0);
+    }
+}
@@ -0,0 +1,245 @@
+// Shaft speed from a slotted photo-interrupter or hall sensor on a GPIO input.
+//
+// let tachometer = Tachometer::spawn(TACHOMETER_PIN, Counter::new(2));
+// motor.run(200);
+// tachometer.command(true);
+// thread::sleep(Duration::from_secs(2));
+// tachometer.status(); // Running(rpm), Stopped or Stalled
+
+use crate::filter::Iir;
+use crate::ring::Ring;
+use crate::worker::Worker;
+use gpio::GpioIn;
+use gpio::GpioValue::High;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const TACHOMETER_PIN: u16 = 19;
+
+// Time between reads of the input. The gpio crate has no edge interrupts, so this polls;
+// a sysfs read plus sleep costs tens of microseconds, and at 500 us the thread stays light.
+// Pulses must stay high and low for at least this long, so about 1000 pulses a second at
+// half duty: 30 000 RPM with two pulses per revolution.
+const POLL: Duration = Duration::from_micros(500);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Running(f32),
+    /// No pulses, and none expected.
+    Stopped,
+    /// No pulses though the motor has been on for longer than the stall timeout.
+    Stalled,
+}
+
+/// Revolutions per minute from `edges` rising edge times, oldest first.
+pub fn rpm(edges: &[Instant], pulses_per_rev: u32) -> Option<f32> {
+    if edges.len() < 2 {
+        return None;
+    }
+    let span = (edges[edges.len() - 1] - edges[0]).as_secs_f32();
+    if span <= 0.0 {
+        return None;
+    }
+    let revolutions = (edges.len() - 1) as f32 / pulses_per_rev as f32;
+    Some(revolutions / span * 60.0)
+}
+
+/// Edge times and the motor command, for speed and stall detection.
+pub struct Counter {
+    edges: Ring<Instant>,
+    pulses_per_rev: u32,
+    smoothing: Iir,
+    /// Longest gap between pulses before the shaft counts as stopped.
+    pub stall_timeout: Duration,
+    rpm: Option<f32>,
+    commanded: Option<Instant>,
+}
+
+impl Counter {
+    /// Average over a whole revolution, so uneven slots or magnets don't show as ripple.
+    pub fn new(pulses_per_rev: u32) -> Counter {
+        let pulses_per_rev = pulses_per_rev.max(1);
+        Counter {
+            edges: Ring::new(pulses_per_rev as usize + 1),
+            pulses_per_rev,
+            smoothing: Iir::new(0.3),
+            stall_timeout: Duration::from_millis(500),
+            rpm: None,
+            commanded: None,
+        }
+    }
+
+    /// Weight of each new speed in the smoothing filter; 1.0 turns it off.
+    pub fn with_smoothing(mut self, alpha: f32) -> Counter {
+        self.smoothing = Iir::new(alpha);
+        self
+    }
+
+    pub fn edge(&mut self, at: Instant) {
+        self.edges.push(at);
+        let edges: Vec<Instant> = self.edges.last(self.edges.len()).copied().collect();
+        if let Some(rpm) = rpm(&edges, self.pulses_per_rev) {
+            self.rpm = Some(self.smoothing.update(rpm));
+        }
+    }
+
+    /// Whether the motor is being driven, so silence means a stall. Counts from `at`.
+    pub fn command(&mut self, on: bool, at: Instant) {
+        self.commanded = match (on, self.commanded) {
+            (true, Some(since)) => Some(since),
+            (true, None) => Some(at),
+            (false, _) => None,
+        };
+    }
+
+    /// Speed, or why there is none. Forgets the old speed once pulses stop.
+    pub fn status(&mut self, now: Instant) -> Status {
+        let last_edge = self.edges.last(1).next().copied();
+        let silent = last_edge.is_none_or(|edge| now - edge > self.stall_timeout);
+        if !silent {
+            return match self.rpm {
+                Some(rpm) => Status::Running(rpm),
+                // One pulse so far; give it a second.
+                None => Status::Stopped,
+            };
+        }
+        // Start afresh next time it turns.
+        self.edges = Ring::new(self.pulses_per_rev as usize + 1);
+        self.smoothing.reset();
+        self.rpm = None;
+        match self.commanded {
+            Some(since) if now - since > self.stall_timeout => Status::Stalled,
+            _ => Status::Stopped,
+        }
+    }
+}
+
+/// Polls the sensor on a background thread.
+pub struct Tachometer {
+    counter: Arc<Mutex<Counter>>,
+    worker: Worker,
+}
+
+impl Tachometer {
+    pub fn spawn(pin: u16, counter: Counter) -> Tachometer {
+        let mut in_ = gpio::sysfs::SysFsGpioInput::open(pin).expect("Pin should be active");
+        let counter = Arc::new(Mutex::new(counter));
+        let thread_counter = Arc::clone(&counter);
+        let worker = Worker::spawn("tachometer", move |running| {
+            let mut was_high = false;
+            while running.load(Ordering::Relaxed) {
+                let high = in_.read_value().expect("Pin should read") == High;
+                if high && !was_high {
+                    thread_counter
+                        .lock()
+                        .expect("Lock should not be poisoned")
+                        .edge(Instant::now());
+                }
+                was_high = high;
+                thread::sleep(POLL);
+            }
+        });
+        Tachometer { counter, worker }
+    }
+
+    /// Tell the tachometer whether the motor is on, for stall detection.
+    pub fn command(&self, on: bool) {
+        self.counter
+            .lock()
+            .expect("Lock should not be poisoned")
+            .command(on, Instant::now());
+    }
+
+    pub fn status(&self) -> Status {
+        self.counter
+            .lock()
+            .expect("Lock should not be poisoned")
+            .status(Instant::now())
+    }
+
+    /// Smoothed RPM, 0.0 when not turning.
+    pub fn rpm(&self) -> f32 {
+        match self.status() {
+            Status::Running(rpm) => rpm,
+            _ => 0.0,
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.worker.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edges(counter: &mut Counter, start: Instant, every: Duration, count: u32) {
+        for i in 0..count {
+            counter.edge(start + every * i);
+        }
+    }
+
+    #[test]
+    fn test_rpm() {
+        let start = Instant::now();
+        // Two pulses a revolution, 10 ms apart: 50 rev/s.
+        let times: Vec<Instant> = (0..5)
+            .map(|i| start + Duration::from_millis(10 * i))
+            .collect();
+        assert!((rpm(&times, 2).unwrap() - 3000.0).abs() < 0.1);
+        assert_eq!(rpm(&times[..1], 2), None);
+    }
+
+    #[test]
+    fn test_counter_smooths_and_stops() {
+        let start = Instant::now();
+        let mut counter = Counter::new(1).with_smoothing(0.5);
+        edges(&mut counter, start, Duration::from_millis(20), 2);
+        let last = start + Duration::from_millis(20);
+        assert!(matches!(counter.status(last), Status::Running(rpm) if (rpm - 3000.0).abs() < 0.1));
+        // Speeds up to 6000 RPM; the filter only goes half way.
+        counter.edge(last + Duration::from_millis(10));
+        assert!(matches!(
+            counter.status(last + Duration::from_millis(10)),
+            Status::Running(rpm) if (rpm - 4500.0).abs() < 0.1
+        ));
+        assert_eq!(
+            counter.status(last + Duration::from_secs(1)),
+            Status::Stopped
+        );
+    }
+
+    #[test]
+    fn test_stall_detection() {
+        let start = Instant::now();
+        let mut counter = Counter::new(2);
+        counter.command(true, start);
+        // Spinning up is not a stall.
+        assert_eq!(
+            counter.status(start + Duration::from_millis(100)),
+            Status::Stopped
+        );
+        assert_eq!(
+            counter.status(start + Duration::from_secs(1)),
+            Status::Stalled
+        );
+        edges(
+            &mut counter,
+            start + Duration::from_secs(1),
+            Duration::from_millis(10),
+            3,
+        );
+        assert!(matches!(
+            counter.status(start + Duration::from_millis(1020)),
+            Status::Running(_)
+        ));
+        counter.command(false, start + Duration::from_secs(2));
+        assert_eq!(
+            counter.status(start + Duration::from_secs(3)),
+            Status::Stopped
+        );
+    }
+}
@@ -1,87 +1,440 @@
 // DHT11 datasheet:
 // https://www.mouser.com/datasheet/2/758/DHT11-Technical-Data-Sheet-Translated-Version-1143054.pdf
+// DHT22/AM2302 datasheet:
+// https://www.sparkfun.com/datasheets/Sensors/Temperature/DHT22.pdf
 
-use gpio::GpioValue::{High, Low};
+use gpio::sysfs::SysFsGpioInput;
+use gpio::GpioValue::{self, High, Low};
 use gpio::{GpioIn, GpioOut};
-use std::str::FromStr;
+use std::fmt;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 const PIN: u16 = 25;
 
-pub fn measure_temp_humid() -> (f32, f32) {
-    let mut data = Vec::new();
-    let mut start_pin = gpio::sysfs::SysFsGpioOutput::open(PIN).unwrap();
-    start_pin.set_value(false).unwrap();
-    thread::sleep(Duration::from_millis(20));
-    start_pin.set_value(true).unwrap();
-    let mut data_pin = gpio::sysfs::SysFsGpioInput::open(PIN).unwrap();
-    while data_pin.read_value().unwrap() == Low {
-        continue;
-    }
-    while data_pin.read_value().unwrap() == High {
-        continue;
-    }
-    loop {
-        while data_pin.read_value().unwrap() == Low {
-            continue;
+// Bits per frame; 4 data bytes and a checksum.
+const FRAME_BITS: usize = 40;
+
+// A '0' is ~26 microseconds high, a '1' ~70.
+const ONE_THRESHOLD_US: u32 = 50;
+const MAX_BIT_US: u32 = 100;
+
+// Give up waiting on the line after this long.
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(1);
+const BIT_TIMEOUT: Duration = Duration::from_micros(500);
+
+// A cached reading older than this many minimum intervals is no longer reported.
+const STALE_INTERVALS: u32 = 5;
+
+/// Sensor models sharing the single wire protocol.
+///
+/// All send a 40 bit frame; humidity, temperature and a checksum byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+    /// DHT11. Integral and decimal (tenths) bytes for each value.
+    ///
+    /// Newer revisions flag sub-zero temperatures with the top bit of the temperature decimal.
+    Dht11,
+    /// DHT22, AM2302, DHT21 and AM2301. 16 bit values in tenths.
+    ///
+    /// The top bit of the temperature word is the sign.
+    Dht22,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reading {
+    /// Degrees celsius.
+    pub temperature: f32,
+    /// Percent relative humidity.
+    pub humidity: f32,
+}
+
+/// A frame whose checksum has been verified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub bytes: [u8; 5],
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DhtError {
+    /// Sensor did not answer the start signal.
+    NoResponse,
+    /// Fewer than 40 bits arrived.
+    ShortFrame(usize),
+    /// A high pulse too long to be a data bit.
+    BadPulse { index: usize, micros: u32 },
+    /// Checksum byte does not match the low byte of the data sum.
+    Checksum { expected: u8, received: u8 },
+}
+
+impl fmt::Display for DhtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DhtError::NoResponse => write!(f, "no response from sensor"),
+            DhtError::ShortFrame(bits) => write!(f, "expected {FRAME_BITS} bits, got {bits}"),
+            DhtError::BadPulse { index, micros } => {
+                write!(f, "bit {index} was high for {micros} us")
+            }
+            DhtError::Checksum { expected, received } => {
+                write!(f, "checksum {received:#04x}, expected {expected:#04x}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DhtError {}
+
+/// Read counts kept by `Dht`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Attempts made, retries included.
+    pub attempts: u32,
+    pub failures: u32,
+    /// Failures since the last good reading.
+    pub consecutive_failures: u32,
+    pub last_error: Option<DhtError>,
+}
+
+/// A sensor driver that paces reads, retries failures and remembers the last good reading.
+pub struct Dht {
+    model: Model,
+    min_interval: Duration,
+    retries: u32,
+    last_attempt: Option<Instant>,
+    last_good: Option<(Reading, Instant)>,
+    stats: Stats,
+}
+
+impl Model {
+    /// How long the host holds the line low to wake the sensor.
+    fn start_signal(&self) -> Duration {
+        match self {
+            Model::Dht11 => Duration::from_millis(20),
+            Model::Dht22 => Duration::from_millis(2),
         }
-        let start = chrono::Utc::now();
-        let mut limit = 0;
-        while data_pin.read_value().unwrap() == High {
-            if limit > 30 {
-                // println!("bit hung");
+    }
+
+    /// Shortest time between reads the sensor tolerates.
+    pub fn min_interval(&self) -> Duration {
+        match self {
+            Model::Dht11 => Duration::from_secs(1),
+            Model::Dht22 => Duration::from_secs(2),
+        }
+    }
+}
+
+impl Dht {
+    /// Two retries by default. After each failure the next read waits twice as long as the one
+    /// before, starting at the model's minimum interval.
+    pub fn new(model: Model) -> Dht {
+        Self {
+            model,
+            min_interval: model.min_interval(),
+            retries: 2,
+            last_attempt: None,
+            last_good: None,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn with_retries(mut self, retries: u32) -> Dht {
+        self.retries = retries;
+        self
+    }
+
+    /// Intervals below the model's minimum are raised to it.
+    pub fn with_min_interval(mut self, min_interval: Duration) -> Dht {
+        self.min_interval = min_interval.max(self.model.min_interval());
+        self
+    }
+
+    /// Time to leave after the last attempt; doubles with each consecutive failure, at most
+    /// `retries` times.
+    fn interval(&self) -> Duration {
+        let backoff = self.stats.consecutive_failures.min(self.retries);
+        self.min_interval * 2_u32.pow(backoff)
+    }
+
+    /// Whether the pacing window has passed.
+    fn due(&self) -> bool {
+        self.last_attempt
+            .is_none_or(|last| last.elapsed() >= self.interval())
+    }
+
+    /// Read the sensor, sleeping out the pacing window before each attempt. Up to `retries`
+    /// failures are retried, each waiting twice as long as the last, before the error is
+    /// returned.
+    pub fn read(&mut self) -> Result<Reading, DhtError> {
+        let mut result = Err(DhtError::NoResponse);
+        for _ in 0..=self.retries {
+            if let Some(last) = self.last_attempt {
+                let interval = self.interval();
+                let elapsed = last.elapsed();
+                if elapsed < interval {
+                    thread::sleep(interval - elapsed);
+                }
+            }
+            result = self.attempt();
+            if result.is_ok() {
                 break;
-            } else {
-                limit += 1
             }
         }
-        let end = chrono::Utc::now();
-        let bit_time = (end - start).num_microseconds().unwrap();
-        // println!("bit time {:?}", bit_time);
-        match bit_time {
-            i64::MIN..=35 => data.push(0),
-            36..=85 => data.push(1),
-            86.. => {
-                break;
+        result
+    }
+
+    /// One read of the sensor, counted in the stats.
+    fn attempt(&mut self) -> Result<Reading, DhtError> {
+        self.last_attempt = Some(Instant::now());
+        self.stats.attempts += 1;
+        match measure_temp_humid(self.model) {
+            Ok(reading) => {
+                self.stats.consecutive_failures = 0;
+                self.last_good = Some((reading, Instant::now()));
+                Ok(reading)
+            }
+            Err(e) => {
+                self.stats.failures += 1;
+                self.stats.consecutive_failures += 1;
+                self.stats.last_error = Some(e.clone());
+                Err(e)
             }
         }
-        if data.len() > 40 {
+    }
+
+    /// Make one attempt if the pacing window has passed, otherwise use the cache. Never sleeps.
+    ///
+    /// Return the freshest good reading and its age, None if there never was one or it is
+    /// older than a few minimum intervals.
+    pub fn latest(&mut self) -> Option<(Reading, Duration)> {
+        if self.due() {
+            let _ = self.attempt();
+        }
+        self.last_good()
+            .filter(|(_, age)| *age <= self.min_interval * STALE_INTERVALS)
+    }
+
+    /// Last good reading and its age, without touching the sensor.
+    pub fn last_good(&self) -> Option<(Reading, Duration)> {
+        self.last_good
+            .map(|(reading, taken)| (reading, taken.elapsed()))
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+}
+
+impl Frame {
+    /// Turn high pulse durations in microseconds into a validated frame.
+    ///
+    /// Only the last 40 pulses are used, so a trace still holding the sensor's ~80 microsecond
+    /// response pulse decodes just the same.
+    pub fn from_pulses(pulses: &[u32]) -> Result<Frame, DhtError> {
+        if pulses.len() < FRAME_BITS {
+            return Err(DhtError::ShortFrame(pulses.len()));
+        }
+        let offset = pulses.len() - FRAME_BITS;
+        let mut bytes = [0_u8; 5];
+        for (i, &micros) in pulses[offset..].iter().enumerate() {
+            let bit = match micros {
+                0..=ONE_THRESHOLD_US => 0,
+                MAX_BIT_US.. => return Err(DhtError::BadPulse { index: i, micros }),
+                _ => 1,
+            };
+            bytes[i / 8] = (bytes[i / 8] << 1) | bit;
+        }
+        let expected = bytes[..4].iter().fold(0_u8, |sum, b| sum.wrapping_add(*b));
+        if expected != bytes[4] {
+            return Err(DhtError::Checksum {
+                expected,
+                received: bytes[4],
+            });
+        }
+        Ok(Frame { bytes })
+    }
+
+    /// Interpret the data bytes for a given sensor model.
+    pub fn reading(&self, model: Model) -> Reading {
+        let b = self.bytes;
+        let (temp, negative, hum) = match model {
+            Model::Dht11 => (
+                b[2] as f32 + (b[3] & 0x7F) as f32 / 10.0,
+                b[3] & 0x80 != 0,
+                b[0] as f32 + b[1] as f32 / 10.0,
+            ),
+            Model::Dht22 => (
+                u16::from_be_bytes([b[2] & 0x7F, b[3]]) as f32 / 10.0,
+                b[2] & 0x80 != 0,
+                u16::from_be_bytes([b[0], b[1]]) as f32 / 10.0,
+            ),
+        };
+        Reading {
+            temperature: if negative { -temp } else { temp },
+            humidity: hum,
+        }
+    }
+}
+
+/// Pure decode of a captured trace.
+pub fn decode(pulses: &[u32], model: Model) -> Result<Reading, DhtError> {
+    Frame::from_pulses(pulses).map(|frame| frame.reading(model))
+}
+
+/// Spin until the pin reads `value`. Return how long that took.
+fn wait_for(
+    pin: &mut SysFsGpioInput,
+    value: GpioValue,
+    timeout: Duration,
+) -> Result<Duration, DhtError> {
+    let start = Instant::now();
+    while pin.read_value().expect("Pin should read") != value {
+        if start.elapsed() > timeout {
+            return Err(DhtError::NoResponse);
+        }
+    }
+    Ok(start.elapsed())
+}
+
+/// Send the start signal and record how long the line is high for each bit.
+///
+/// Stops after 40 bits or when the line goes quiet; the decoder judges what arrived.
+pub fn capture_pulses(model: Model) -> Result<Vec<u32>, DhtError> {
+    let mut pulses = Vec::with_capacity(FRAME_BITS);
+    let mut start_pin = gpio::sysfs::SysFsGpioOutput::open(PIN).expect("Pin should be active");
+    start_pin.set_value(false).expect("Pin should set");
+    thread::sleep(model.start_signal());
+    start_pin.set_value(true).expect("Pin should set");
+    let mut data_pin = SysFsGpioInput::open(PIN).expect("Pin should be active");
+
+    // Sensor response; ~80 microseconds low then ~80 high.
+    wait_for(&mut data_pin, Low, RESPONSE_TIMEOUT)?;
+    wait_for(&mut data_pin, High, RESPONSE_TIMEOUT)?;
+    wait_for(&mut data_pin, Low, RESPONSE_TIMEOUT)?;
+
+    while pulses.len() < FRAME_BITS {
+        // Each bit starts with ~50 microseconds low.
+        if wait_for(&mut data_pin, High, BIT_TIMEOUT).is_err() {
             break;
         }
+        match wait_for(&mut data_pin, Low, BIT_TIMEOUT) {
+            Ok(high) => pulses.push(high.as_micros() as u32),
+            Err(_) => break,
+        }
     }
-    if data.len() < 40 {
-        println!("\nError reading temp/humidity; not enough data received.");
-        return (0.0, 0.0);
-    }
-    let hum_bit = Vec::from(&data[0..8]);
-    let hum_dec_bit = Vec::from(&data[8..16]);
-    let temp_bit = Vec::from(&data[16..24]);
-    let temp_dec_bit = Vec::from(&data[24..32]);
-    let check_bit = Vec::from(&data[32..40]);
-    let mut hum = 0;
-    let mut hum_dec = 0;
-    let mut temp = 0;
-    let mut temp_dec = 0;
-    let mut check = 0;
-
-    for i in 0..8 {
-        hum += hum_bit[i] * i32::pow(2, 7 - i as u32);
-        hum_dec += hum_dec_bit[i] * i32::pow(2, 7 - i as u32);
-        temp += temp_bit[i] * i32::pow(2, 7 - i as u32);
-        temp_dec += temp_dec_bit[i] * i32::pow(2, 7 - i as u32);
-        check += check_bit[i] * i32::pow(2, 7 - i as u32);
-    }
-    if check != hum + hum_dec + temp + temp_dec {
-        println!("\nError reading temp/humidity; checksum error.");
-        println!(
-            "temp {}.{}\nhum {}.{}\ncheck {}",
-            temp, temp_dec, hum, hum_dec, check
+    Ok(pulses)
+}
+
+pub fn measure_temp_humid(model: Model) -> Result<Reading, DhtError> {
+    let pulses = capture_pulses(model)?;
+    decode(&pulses, model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 45.0 %, 23.1 C.
+    const DHT11_TRACE: [u32; 40] = [
+        27, 24, 74, 22, 69, 76, 23, 73, 22, 30, 25, 22, 23, 28, 28, 23, 25, 23, 30, 74, 22, 69, 71,
+        68, 28, 22, 25, 22, 30, 24, 26, 74, 24, 76, 23, 26, 30, 70, 23, 71,
+    ];
+
+    // 65.2 %, 35.1 C.
+    const DHT22_TRACE: [u32; 40] = [
+        29, 23, 23, 26, 29, 23, 68, 26, 75, 26, 28, 27, 68, 75, 27, 24, 23, 29, 22, 25, 26, 24, 25,
+        74, 28, 75, 23, 70, 75, 74, 76, 72, 70, 74, 76, 26, 74, 73, 74, 25,
+    ];
+
+    // 41.0 %, -10.1 C. Leads with the response pulse. Checksum overflows a byte.
+    const DHT22_NEGATIVE_TRACE: [u32; 41] = [
+        82, 27, 23, 30, 23, 22, 25, 29, 76, 74, 27, 29, 75, 73, 26, 71, 24, 71, 23, 26, 30, 29, 27,
+        29, 26, 23, 69, 76, 28, 24, 73, 24, 75, 74, 22, 23, 30, 27, 27, 27, 29,
+    ];
+
+    #[test]
+    fn test_decode_dht11() {
+        let reading = decode(&DHT11_TRACE, Model::Dht11).unwrap();
+        assert_eq!(reading.humidity, 45.0);
+        assert_eq!(reading.temperature, 23.1);
+    }
+
+    #[test]
+    fn test_decode_dht22() {
+        let reading = decode(&DHT22_TRACE, Model::Dht22).unwrap();
+        assert_eq!(reading.humidity, 65.2);
+        assert_eq!(reading.temperature, 35.1);
+    }
+
+    #[test]
+    fn test_decode_dht22_negative() {
+        let frame = Frame::from_pulses(&DHT22_NEGATIVE_TRACE).unwrap();
+        assert_eq!(frame.bytes, [0x01, 0x9A, 0x80, 0x65, 0x80]);
+        let reading = frame.reading(Model::Dht22);
+        assert_eq!(reading.humidity, 41.0);
+        assert_eq!(reading.temperature, -10.1);
+    }
+
+    #[test]
+    fn test_short_frame() {
+        assert_eq!(
+            decode(&DHT11_TRACE[..32], Model::Dht11),
+            Err(DhtError::ShortFrame(32))
+        );
+    }
+
+    #[test]
+    fn test_checksum_error() {
+        let mut trace = DHT11_TRACE;
+        // Flip the last bit of the humidity byte.
+        trace[7] = 24;
+        assert_eq!(
+            decode(&trace, Model::Dht11),
+            Err(DhtError::Checksum {
+                expected: 68,
+                received: 69
+            })
+        );
+    }
+
+    #[test]
+    fn test_backoff_interval() {
+        let mut dht = Dht::new(Model::Dht22);
+        assert_eq!(dht.interval(), Duration::from_secs(2));
+        dht.stats.consecutive_failures = 1;
+        assert_eq!(dht.interval(), Duration::from_secs(4));
+        dht.stats.consecutive_failures = 5;
+        assert_eq!(dht.interval(), Duration::from_secs(8));
+        assert!(dht.due());
+        dht.last_attempt = Some(Instant::now());
+        assert!(!dht.due());
+    }
+
+    #[test]
+    fn test_latest_drops_stale_readings() {
+        let mut dht = Dht::new(Model::Dht11);
+        let reading = Reading {
+            temperature: 20.0,
+            humidity: 50.0,
+        };
+        let now = Instant::now();
+        dht.last_attempt = Some(now);
+        dht.last_good = Some((reading, now - Duration::from_secs(2)));
+        assert_eq!(dht.latest().map(|(r, _)| r), Some(reading));
+        dht.last_good = Some((reading, now - Duration::from_secs(10)));
+        assert_eq!(dht.latest(), None);
+        assert!(dht.last_good().is_some());
+    }
+
+    #[test]
+    fn test_bad_pulse() {
+        let mut trace = DHT22_TRACE;
+        trace[12] = 140;
+        assert_eq!(
+            decode(&trace, Model::Dht22),
+            Err(DhtError::BadPulse {
+                index: 12,
+                micros: 140
+            })
         );
-        return (0.0, 0.0);
-    };
-    let hum = f32::from_str(&format!("{}.{}", hum, hum_dec)).expect("should be float");
-    let temp = f32::from_str(&format!("{}.{}", temp, temp_dec)).expect("should be float");
-    // println!("temp {}\nhumid {}\n", temp, hum);
-    (temp, hum)
+    }
 }
@@ -0,0 +1,53 @@
+// Helpers shared by the unit tests.
+
+use crate::pwm::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub fn assert_near(actual: f32, expected: f32, tolerance: f32) {
+    assert!(
+        (actual - expected).abs() <= tolerance,
+        "{actual} not within {tolerance} of {expected}"
+    );
+}
+
+/// A pin that records every level written, stamped with a clock that only `sleep` moves.
+/// Clones share the record and the clock.
+#[derive(Clone, Default)]
+pub struct SimulatedPin {
+    levels: Arc<Mutex<Vec<(Duration, bool)>>>,
+    clock: Arc<Mutex<Duration>>,
+}
+
+impl Pin for SimulatedPin {
+    fn set(&mut self, high: bool) {
+        let now = *self.clock.lock().unwrap();
+        self.levels.lock().unwrap().push((now, high));
+    }
+}
+
+impl SimulatedPin {
+    /// The last level written; low before any.
+    pub fn level(&self) -> bool {
+        self.levels.lock().unwrap().last().is_some_and(|l| l.1)
+    }
+
+    /// Stands in for `thread::sleep`.
+    pub fn sleep(&self, time: Duration) {
+        *self.clock.lock().unwrap() += time;
+    }
+
+    /// Fraction of the time from the first write to now spent high.
+    pub fn duty(&self) -> f32 {
+        let levels = self.levels.lock().unwrap();
+        let now = *self.clock.lock().unwrap();
+        let ends = levels.iter().skip(1).map(|l| l.0).chain([now]);
+        let high: Duration = levels
+            .iter()
+            .zip(ends)
+            .filter(|(start, _)| start.1)
+            .map(|(start, end)| end - start.0)
+            .sum();
+        high.as_secs_f32() / (now - levels[0].0).as_secs_f32()
+    }
+}
@@ -0,0 +1,262 @@
+// Convert ADC counts from the kit's analog sensors into physical units.
+//
+// Every sensor here is a resistor in a divider across the same rail as the ADC reference, so
+// only the count's ratio to full scale matters, not the reference voltage.
+// LDR: https://www.allaboutcircuits.com/projects/design-a-luxmeter-using-a-light-dependent-resistor/
+// MQ-2: https://www.pololu.com/file/0J309/MQ2.pdf
+
+const FULL_SCALE: f32 = 255.0;
+const KELVIN: f32 = 273.15;
+
+/// Which leg of the divider the sensor is; the fixed resistor is the other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    /// Between the supply and the ADC input.
+    Top,
+    /// Between the ADC input and ground.
+    Bottom,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Divider {
+    /// Ohms.
+    pub fixed: f32,
+    pub side: Side,
+}
+
+impl Divider {
+    /// Sensor resistance in ohms, None at either rail where it can't be told.
+    pub fn resistance(&self, raw: u8) -> Option<f32> {
+        if raw == 0 || raw == u8::MAX {
+            return None;
+        }
+        let ratio = raw as f32 / FULL_SCALE;
+        Some(match self.side {
+            Side::Top => self.fixed * (1.0 - ratio) / ratio,
+            Side::Bottom => self.fixed * ratio / (1.0 - ratio),
+        })
+    }
+}
+
+/// Photoresistor, resistance falling as a power of illuminance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ldr {
+    pub divider: Divider,
+    /// Ohms at 10 lux.
+    pub r10: f32,
+    /// Slope of log resistance against log lux.
+    pub gamma: f32,
+}
+
+impl Default for Ldr {
+    // GL5528 over a 10k resistor to ground.
+    fn default() -> Ldr {
+        Ldr {
+            divider: Divider {
+                fixed: 10_000.0,
+                side: Side::Top,
+            },
+            r10: 15_000.0,
+            gamma: 0.7,
+        }
+    }
+}
+
+impl Ldr {
+    /// Rough estimate; individual cells vary by a factor of two.
+    pub fn lux(&self, raw: u8) -> Option<f32> {
+        let resistance = self.divider.resistance(raw)?;
+        Some(10.0 * f32::powf(self.r10 / resistance, 1.0 / self.gamma))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ThermistorModel {
+    /// Resistance `r0` at `t0` celsius and the datasheet's B constant.
+    Beta { r0: f32, t0: f32, beta: f32 },
+    /// 1/T = a + b ln R + c (ln R)^3, fitted from three known points.
+    SteinhartHart { a: f32, b: f32, c: f32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Thermistor {
+    pub divider: Divider,
+    pub model: ThermistorModel,
+}
+
+impl Default for Thermistor {
+    // 10k NTC, B 3950, over a 10k resistor to ground.
+    fn default() -> Thermistor {
+        Thermistor {
+            divider: Divider {
+                fixed: 10_000.0,
+                side: Side::Top,
+            },
+            model: ThermistorModel::Beta {
+                r0: 10_000.0,
+                t0: 25.0,
+                beta: 3950.0,
+            },
+        }
+    }
+}
+
+impl Thermistor {
+    pub fn celsius_from_resistance(&self, resistance: f32) -> f32 {
+        let inverse = match self.model {
+            ThermistorModel::Beta { r0, t0, beta } => {
+                1.0 / (t0 + KELVIN) + f32::ln(resistance / r0) / beta
+            }
+            ThermistorModel::SteinhartHart { a, b, c } => {
+                let ln = f32::ln(resistance);
+                a + b * ln + c * ln.powi(3)
+            }
+        };
+        1.0 / inverse - KELVIN
+    }
+
+    pub fn celsius(&self, raw: u8) -> Option<f32> {
+        Some(self.celsius_from_resistance(self.divider.resistance(raw)?))
+    }
+}
+
+/// Linear scale between two counts, for pots and the rain and soil moisture probes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Percent {
+    /// Count read at 0 %.
+    pub empty: u8,
+    /// Count read at 100 %. May be below `empty`; moisture probes read lower when wet.
+    pub full: u8,
+}
+
+impl Percent {
+    /// Whole ADC range.
+    pub fn potentiometer() -> Percent {
+        Percent {
+            empty: 0,
+            full: u8::MAX,
+        }
+    }
+
+    /// Counts measured with the probe in dry air and in water.
+    pub fn moisture(dry: u8, wet: u8) -> Percent {
+        Percent {
+            empty: dry,
+            full: wet,
+        }
+    }
+
+    /// Clamped to 0 through 100.
+    pub fn percent(&self, raw: u8) -> f32 {
+        if self.empty == self.full {
+            return 0.0;
+        }
+        let span = self.full as f32 - self.empty as f32;
+        ((raw as f32 - self.empty as f32) / span * 100.0).clamp(0.0, 100.0)
+    }
+}
+
+/// MQ series gas sensor, ppm = a * (Rs / R0)^b read off the datasheet's log-log curve.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mq {
+    /// The load resistor RL is the fixed leg, the sensor the top one.
+    pub divider: Divider,
+    /// Sensor resistance in clean air divided by `clean_air_ratio`.
+    pub r0: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+impl Mq {
+    /// Rs/R0 in clean air for the MQ-2.
+    pub const MQ2_CLEAN_AIR: f32 = 9.83;
+
+    fn mq2(r0: f32, a: f32, b: f32) -> Mq {
+        Mq {
+            divider: Divider {
+                fixed: 5_000.0,
+                side: Side::Top,
+            },
+            r0,
+            a,
+            b,
+        }
+    }
+
+    pub fn mq2_lpg(r0: f32) -> Mq {
+        Mq::mq2(r0, 574.25, -2.222)
+    }
+
+    pub fn mq2_smoke(r0: f32) -> Mq {
+        Mq::mq2(r0, 3616.1, -2.675)
+    }
+
+    /// R0 from a count taken in clean air after the heater has warmed up.
+    pub fn calibrate(&mut self, raw: u8, clean_air_ratio: f32) -> Option<f32> {
+        self.r0 = self.divider.resistance(raw)? / clean_air_ratio;
+        Some(self.r0)
+    }
+
+    pub fn ppm(&self, raw: u8) -> Option<f32> {
+        let ratio = self.divider.resistance(raw)? / self.r0;
+        Some(self.a * f32::powf(ratio, self.b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::assert_near;
+
+    #[test]
+    fn test_divider() {
+        let divider = Divider {
+            fixed: 10_000.0,
+            side: Side::Bottom,
+        };
+        assert_near(divider.resistance(51).unwrap(), 2_500.0, 1.0);
+        assert_eq!(divider.resistance(0), None);
+        assert_eq!(divider.resistance(255), None);
+    }
+
+    #[test]
+    fn test_thermistor_models_agree() {
+        let beta = Thermistor::default();
+        // Mid scale is equal resistances, so the nominal 25 C.
+        assert_near(beta.celsius_from_resistance(10_000.0), 25.0, 0.01);
+        // Datasheet-style coefficients for a 10k NTC.
+        let steinhart = Thermistor {
+            model: ThermistorModel::SteinhartHart {
+                a: 1.125_308_5e-3,
+                b: 2.347_14e-4,
+                c: 8.566_0e-8,
+            },
+            ..beta
+        };
+        assert_near(steinhart.celsius_from_resistance(10_000.0), 25.0, 0.1);
+        assert_near(beta.celsius_from_resistance(3_000.0), 54.0, 1.0);
+        assert_near(steinhart.celsius_from_resistance(3_000.0), 54.0, 1.5);
+    }
+
+    #[test]
+    fn test_percent() {
+        assert_eq!(Percent::potentiometer().percent(255), 100.0);
+        let soil = Percent::moisture(230, 100);
+        assert_eq!(soil.percent(250), 0.0);
+        assert_eq!(soil.percent(165), 50.0);
+        assert_eq!(soil.percent(50), 100.0);
+    }
+
+    #[test]
+    fn test_mq_reads_clean_air_after_calibration() {
+        let mut sensor = Mq::mq2_lpg(1.0);
+        sensor.calibrate(40, Mq::MQ2_CLEAN_AIR);
+        // Clean air sits on the curve at Rs/R0 = 9.83.
+        assert_near(
+            sensor.ppm(40).unwrap(),
+            574.25 * f32::powf(9.83, -2.222),
+            0.01,
+        );
+        assert!(sensor.ppm(120).unwrap() > sensor.ppm(40).unwrap());
+    }
+}
@@ -0,0 +1,74 @@
+// Background thread with a stop flag, shared by the modules that poll hardware.
+//
+// let worker = Worker::spawn("poller", move |running| {
+//     while running.load(Ordering::Relaxed) { ... }
+// });
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Owns a named thread that loops until asked to stop. Dropping it stops the thread.
+pub struct Worker {
+    running: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    /// Run `body` on a thread called `name`. It should return soon after `running` reads false.
+    pub fn spawn<F>(name: &str, body: F) -> Worker
+    where
+        F: FnOnce(&AtomicBool) + Send + 'static,
+    {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = Arc::clone(&running);
+        let handle = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || body(&thread_running))
+            .expect("Thread should exist");
+        Worker {
+            running,
+            handle: Some(handle),
+        }
+    }
+
+    /// Clear the flag and wait for the thread to return.
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            // A panic on the thread has already been printed; don't panic again in drop.
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+    use std::time::Duration;
+
+    #[test]
+    fn test_stop_joins_thread() {
+        let loops = Arc::new(AtomicU32::new(0));
+        let thread_loops = Arc::clone(&loops);
+        let mut worker = Worker::spawn("test worker", move |running| {
+            while running.load(Ordering::Relaxed) {
+                thread_loops.fetch_add(1, Ordering::Relaxed);
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        worker.stop();
+        let after = loops.load(Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(loops.load(Ordering::Relaxed), after);
+        // Stopping twice, and then dropping, is harmless.
+        worker.stop();
+    }
+}